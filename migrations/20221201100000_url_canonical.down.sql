@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS idx_links_url_canonical;
+ALTER TABLE links DROP COLUMN url_canonical;
@@ -0,0 +1,3 @@
+ALTER TABLE links ADD COLUMN url_canonical TEXT NOT NULL DEFAULT '';
+CREATE UNIQUE INDEX IF NOT EXISTS idx_links_url_canonical ON links(url_canonical)
+    WHERE url_canonical != '';
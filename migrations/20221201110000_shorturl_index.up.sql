@@ -0,0 +1 @@
+CREATE INDEX IF NOT EXISTS idx_links_shorturl ON links(shorturl);
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS idx_links_shorturl;
+CREATE INDEX IF NOT EXISTS idx_links_shorturl ON links(shorturl);
@@ -0,0 +1,5 @@
+-- Short urls are stored once and never computed again from the id, so the
+-- existing ones are kept as they are. Empty ones are filled at startup.
+DROP INDEX IF EXISTS idx_links_shorturl;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_links_shorturl ON links(shorturl)
+    WHERE shorturl != '';
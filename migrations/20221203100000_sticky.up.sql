@@ -0,0 +1 @@
+ALTER TABLE links ADD COLUMN sticky BOOLEAN NOT NULL DEFAULT FALSE;
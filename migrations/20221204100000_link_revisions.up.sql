@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS link_revisions(
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    link_id INTEGER NOT NULL,
+    url TEXT NOT NULL,
+    title TEXT NOT NULL,
+    description TEXT NOT NULL,
+    tags TEXT NOT NULL,
+    private BOOLEAN NOT NULL,
+    sticky BOOLEAN NOT NULL,
+    updated DATETIME NOT NULL,
+    dt DATETIME NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_link_revisions_link_id ON link_revisions(link_id);
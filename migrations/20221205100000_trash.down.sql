@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS idx_links_deleted_at;
+DELETE FROM links WHERE deleted_at IS NOT NULL;
+ALTER TABLE links DROP COLUMN deleted_at;
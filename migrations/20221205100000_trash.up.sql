@@ -0,0 +1,2 @@
+ALTER TABLE links ADD COLUMN deleted_at DATETIME;
+CREATE INDEX IF NOT EXISTS idx_links_deleted_at ON links(deleted_at);
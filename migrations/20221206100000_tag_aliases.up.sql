@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS tag_aliases(
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    alias TEXT NOT NULL COLLATE NOCASE UNIQUE,
+    tag_id INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_tag_aliases_tag_id ON tag_aliases(tag_id);
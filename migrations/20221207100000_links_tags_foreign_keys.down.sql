@@ -0,0 +1,21 @@
+CREATE TABLE IF NOT EXISTS links_tags_old(
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    link_id INTEGER NOT NULL,
+    tag_id INTEGER NOT NULL,
+    UNIQUE(link_id, tag_id)
+);
+INSERT INTO links_tags_old (id, link_id, tag_id)
+    SELECT id, link_id, tag_id FROM links_tags;
+DROP TABLE links_tags;
+ALTER TABLE links_tags_old RENAME TO links_tags;
+
+CREATE TABLE IF NOT EXISTS tag_aliases_old(
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    alias TEXT NOT NULL COLLATE NOCASE UNIQUE,
+    tag_id INTEGER NOT NULL
+);
+INSERT INTO tag_aliases_old (id, alias, tag_id)
+    SELECT id, alias, tag_id FROM tag_aliases;
+DROP TABLE tag_aliases;
+ALTER TABLE tag_aliases_old RENAME TO tag_aliases;
+CREATE INDEX IF NOT EXISTS idx_tag_aliases_tag_id ON tag_aliases(tag_id);
@@ -0,0 +1,30 @@
+DELETE FROM links_tags
+WHERE link_id NOT IN (SELECT id FROM links)
+    OR tag_id NOT IN (SELECT id FROM tags);
+CREATE TABLE IF NOT EXISTS links_tags_new(
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    link_id INTEGER NOT NULL REFERENCES links(id) ON DELETE CASCADE,
+    tag_id INTEGER NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+    UNIQUE(link_id, tag_id)
+);
+INSERT INTO links_tags_new (id, link_id, tag_id)
+    SELECT id, link_id, tag_id FROM links_tags;
+DROP TABLE links_tags;
+ALTER TABLE links_tags_new RENAME TO links_tags;
+CREATE INDEX IF NOT EXISTS idx_links_tags_tag_id ON links_tags(tag_id);
+
+DELETE FROM tag_aliases WHERE tag_id NOT IN (SELECT id FROM tags);
+CREATE TABLE IF NOT EXISTS tag_aliases_new(
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    alias TEXT NOT NULL COLLATE NOCASE UNIQUE,
+    tag_id INTEGER NOT NULL REFERENCES tags(id) ON DELETE CASCADE
+);
+INSERT INTO tag_aliases_new (id, alias, tag_id)
+    SELECT id, alias, tag_id FROM tag_aliases;
+DROP TABLE tag_aliases;
+ALTER TABLE tag_aliases_new RENAME TO tag_aliases;
+CREATE INDEX IF NOT EXISTS idx_tag_aliases_tag_id ON tag_aliases(tag_id);
+
+DELETE FROM tags
+WHERE id NOT IN (SELECT tag_id FROM links_tags)
+    AND id NOT IN (SELECT tag_id FROM tag_aliases);
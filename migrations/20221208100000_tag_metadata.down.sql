@@ -0,0 +1,3 @@
+ALTER TABLE tags DROP COLUMN icon;
+ALTER TABLE tags DROP COLUMN color;
+ALTER TABLE tags DROP COLUMN description;
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS saved_searches(
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    name TEXT NOT NULL UNIQUE,
+    searchterm TEXT,
+    searchtags TEXT,
+    visibility TEXT,
+    sort TEXT,
+    sort_order TEXT,
+    feed BOOLEAN NOT NULL DEFAULT 0,
+    created DATETIME NOT NULL,
+    updated DATETIME NOT NULL
+);
@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS idx_history_link_id;
+ALTER TABLE history DROP COLUMN payload;
+ALTER TABLE history DROP COLUMN link_id;
@@ -0,0 +1,3 @@
+ALTER TABLE history ADD COLUMN link_id INTEGER;
+ALTER TABLE history ADD COLUMN payload TEXT;
+CREATE INDEX IF NOT EXISTS idx_history_link_id ON history(link_id);
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS history_horizon(
+    id INTEGER PRIMARY KEY NOT NULL CHECK (id = 1),
+    last_id INTEGER NOT NULL,
+    last_dt DATETIME NOT NULL
+);
@@ -1,6 +1,7 @@
 use actix_web::{HttpServer, App, web::{self, Data}, middleware::Logger};
-use sqlx::{query, sqlite::{SqlitePool, SqlitePoolOptions}, migrate::{Migrator, MigrateDatabase}};
-use std::{env, path::Path, process};
+use sqlx::{query, sqlite::{SqlitePool, SqlitePoolOptions, SqliteConnectOptions},
+    migrate::{Migrator, MigrateDatabase}};
+use std::{env, path::Path, process, str::FromStr, time::Duration};
 use tokio::fs;
 use env_logger::Env;
 use log::{debug, error};
@@ -11,6 +12,9 @@ use dotenv::dotenv;
 mod models;
 mod routes;
 
+use models::{link::Link, alias::Alias, tag::Tag, webhook::Webhook,
+    history::{History, Retention}, short_url};
+
 
 
 #[actix_web::main]
@@ -25,10 +29,22 @@ async fn main() -> std::io::Result<()> {
     debug!("Port: {}", port);
     let secret = env::var("SECRET").expect("SECRET not set");
     debug!("Secret: {}", secret);
-    let seed = env::var("SEED").expect("SEED not set");
-    debug!("Seed: {}", seed);
+    let trash_days = env::var("TRASH_DAYS")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .unwrap_or(30);
+    debug!("Trash days: {}", trash_days);
+    let retention = Retention::from_env();
+    debug!("History retention: {:?}", retention);
+    match short_url::strategy(){
+        Ok(strategy) => debug!("Short url strategy: {:?}", strategy),
+        Err(e) => {
+            error!("Can not select the short url strategy, {}", e);
+            process::exit(1);
+        }
+    }
 
-    let template = match Tera::new("templates/**/*.html"){
+    let template = match Tera::new("templates/**/*"){
         Ok(t) => t,
         Err(e) => {
             error!("Can not load templates, {}", e);
@@ -47,9 +63,12 @@ async fn main() -> std::io::Result<()> {
     };
     debug!("{}", &migrations.display());
 
+    let options = SqliteConnectOptions::from_str(&db_url)
+        .expect("Invalid DATABASE_URL")
+        .foreign_keys(true);
     let pool = SqlitePoolOptions::new()
         .max_connections(4)
-        .connect(&db_url)
+        .connect_with(options)
         .await
         .expect("Pool failed");
 
@@ -59,6 +78,63 @@ async fn main() -> std::io::Result<()> {
         .run(&pool)
         .await
         .unwrap();
+    Link::canonicalize_all(&Data::new(pool.clone())).await;
+    Link::freeze_shorturls(&Data::new(pool.clone())).await;
+
+    // One-shot commands run over the database and exit
+    if let Some(command) = env::args().nth(1){
+        match command.as_str(){
+            "fold-aliases" => match Alias::fold(&Data::new(pool.clone())).await{
+                Ok(folded) => println!("Folded {} alias tags into their tags", folded),
+                Err(e) => {
+                    error!("Can not fold the aliases: {}", e);
+                    process::exit(1);
+                },
+            },
+            "normalize-tags" => match Tag::normalize_existing(&Data::new(pool.clone())).await{
+                Ok(changed) => println!("Normalized {} tags", changed),
+                Err(e) => {
+                    error!("Can not normalize the tags: {}", e);
+                    process::exit(1);
+                },
+            },
+            _ => {
+                error!("Unknown command {}", command);
+                process::exit(1);
+            },
+        }
+        return Ok(());
+    }
+
+    if trash_days > 0 {
+        let pool = Data::new(pool.clone());
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(3600));
+            loop {
+                interval.tick().await;
+                match Link::purge_trash(&pool, trash_days).await{
+                    Ok(purged) => debug!("Purged {} links", purged),
+                    Err(e) => error!("Can not purge the trash: {}", e),
+                }
+            }
+        });
+    }
+
+    if retention.enabled() {
+        let pool = Data::new(pool.clone());
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(3600));
+            loop {
+                interval.tick().await;
+                match History::retain(&pool, &retention).await{
+                    Ok(removed) => debug!("Removed {} history events", removed),
+                    Err(e) => error!("Can not apply the history retention: {}", e),
+                }
+            }
+        });
+    }
+
+    Webhook::start(Data::new(pool.clone()));
 
     HttpServer::new(move || {
         App::new()
@@ -73,18 +149,53 @@ async fn main() -> std::io::Result<()> {
                     web::scope("v1")
                     .service(routes::general::get_info)
                     .service(routes::links::create)
+                    .service(routes::links::bulk)
                     .service(routes::links::read)
                     .service(routes::links::read_one)
+                    .service(routes::links::read_from_shorturl)
                     .service(routes::links::update)
                     .service(routes::links::delete)
+                    .service(routes::links::pin)
+                    .service(routes::links::unpin)
+                    .service(routes::revisions::read)
+                    .service(routes::revisions::restore)
+                    .service(routes::trash::read)
+                    .service(routes::trash::restore)
+                    .service(routes::trash::purge)
                     .service(routes::tags::search)
+                    .service(routes::tags::suggest)
+                    .service(routes::tags::complete)
                     .service(routes::tags::read)
+                    .service(routes::tags::related)
                     .service(routes::tags::update)
                     .service(routes::tags::delete)
+                    .service(routes::aliases::read_all)
+                    .service(routes::aliases::create)
+                    .service(routes::aliases::read)
+                    .service(routes::aliases::update)
+                    .service(routes::aliases::delete)
                     .service(routes::history::search)
+                    .service(routes::events::stream)
+                    .service(routes::webhooks::read_all)
+                    .service(routes::webhooks::create)
+                    .service(routes::webhooks::read)
+                    .service(routes::webhooks::update)
+                    .service(routes::webhooks::delete)
+                    .service(routes::webhooks::deliveries)
+                    .service(routes::sync::read)
+                    .service(routes::sync::apply)
+                    .service(routes::saved::read_all)
+                    .service(routes::saved::create)
+                    .service(routes::saved::read)
+                    .service(routes::saved::update)
+                    .service(routes::saved::delete)
+                    .service(routes::saved::links)
+                    .service(routes::saved::feed)
                     )
             )
             //.service(routes::get_results))
+            .service(routes::shaare::redirect)
+            .service(routes::shaare::note)
             .service(actix_files::Files::new("/static", "./static"))
     })
     .workers(4)
@@ -0,0 +1,176 @@
+use serde::{Serialize, Deserialize};
+use actix_web::web;
+use sqlx::{sqlite::{SqlitePool, SqliteRow, Sqlite},
+    Executor, Error, query, Row};
+use log::debug;
+
+use super::{tag::Tag, normalization};
+
+/// Another name of a tag, as `js` for `javascript`. Aliases are compared
+/// without case, so the alias `JS` also stands for `js`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Alias {
+    pub id: i64,
+    pub alias: String,
+    pub tag: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AliasNew {
+    pub alias: String,
+    pub tag: String,
+}
+
+impl Alias{
+    fn from_row(row: SqliteRow) -> Alias{
+        Alias {
+            id: row.get("id"),
+            alias: row.get("alias"),
+            tag: row.get("tag"),
+        }
+    }
+
+    pub async fn create(pool: &web::Data<SqlitePool>, alias: &AliasNew)
+            -> Result<Alias, Error>{
+        let (name, tag) = Self::target(pool, alias).await?;
+        let sql = "INSERT INTO tag_aliases (alias, tag_id) VALUES ($1, $2)";
+        query(sql)
+            .bind(&name)
+            .bind(tag.id)
+            .execute(pool.get_ref())
+            .await?;
+        Self::read(pool, &name).await
+    }
+
+    pub async fn read(pool: &web::Data<SqlitePool>, alias: &str)
+            -> Result<Alias, Error>{
+        let sql = "SELECT a.id id, a.alias alias, t.name tag FROM tag_aliases a
+                   INNER JOIN tags t ON t.id = a.tag_id
+                   WHERE a.alias = $1";
+        query(sql)
+            .bind(alias)
+            .map(Self::from_row)
+            .fetch_one(pool.get_ref())
+            .await
+    }
+
+    pub async fn read_all(pool: &web::Data<SqlitePool>)
+            -> Result<Vec<Alias>, Error>{
+        let sql = "SELECT a.id id, a.alias alias, t.name tag FROM tag_aliases a
+                   INNER JOIN tags t ON t.id = a.tag_id
+                   ORDER BY t.name, a.alias";
+        query(sql)
+            .map(Self::from_row)
+            .fetch_all(pool.get_ref())
+            .await
+    }
+
+    pub async fn update(pool: &web::Data<SqlitePool>, old: &str,
+            alias: &AliasNew) -> Result<Alias, Error>{
+        let (name, tag) = Self::target(pool, alias).await?;
+        let sql = "UPDATE tag_aliases SET alias = $1, tag_id = $2
+                   WHERE alias = $3";
+        let result = query(sql)
+            .bind(&name)
+            .bind(tag.id)
+            .bind(old)
+            .execute(pool.get_ref())
+            .await?;
+        if result.rows_affected() == 0{
+            return Err(Error::RowNotFound);
+        }
+        Self::read(pool, &name).await
+    }
+
+    pub async fn delete(pool: &web::Data<SqlitePool>, alias: &str)
+            -> Result<bool, Error>{
+        let sql = "DELETE FROM tag_aliases WHERE alias = $1";
+        let result = query(sql)
+            .bind(alias)
+            .execute(pool.get_ref())
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Tag the name is an alias of, if any
+    pub async fn resolve<'c, E>(executor: E, name: &str)
+            -> Result<Option<Tag>, Error>
+            where E: Executor<'c, Database = Sqlite>{
+        let sql = "SELECT t.id id, t.name name FROM tags t
+                   INNER JOIN tag_aliases a ON a.tag_id = t.id
+                   WHERE a.alias = $1";
+        query(sql)
+            .bind(name)
+            .map(|row: SqliteRow| Tag{
+                id: row.get("id"),
+                name: row.get("name"),
+            })
+            .fetch_optional(executor)
+            .await
+    }
+
+    /// Merge the tags named as an alias into the tag of the alias. Returns
+    /// the number of tags merged.
+    pub async fn fold(pool: &web::Data<SqlitePool>) -> Result<usize, Error>{
+        let sql = "SELECT t.name alias, c.name tag FROM tag_aliases a
+                   INNER JOIN tags t ON t.name = a.alias COLLATE NOCASE
+                   INNER JOIN tags c ON c.id = a.tag_id
+                   WHERE t.id != c.id";
+        let pairs: Vec<(String, String)> = query(sql)
+            .map(|row: SqliteRow| (row.get("alias"), row.get("tag")))
+            .fetch_all(pool.get_ref())
+            .await?;
+        for (alias, tag) in pairs.iter(){
+            debug!("Fold tag {} into {}", alias, tag);
+            Tag::update(pool, alias, tag).await?;
+        }
+        Ok(pairs.len())
+    }
+
+    /// Normalized alias and the tag it points to, created if needed. An
+    /// alias of an alias points to the final tag.
+    async fn target(pool: &web::Data<SqlitePool>, alias: &AliasNew)
+            -> Result<(String, Tag), Error>{
+        let name = normalization::normalize(&alias.alias);
+        if name.is_empty(){
+            return Err(Error::Protocol("Invalid alias".to_string()));
+        }
+        let tag = Tag::get_or_insert(pool, &alias.tag).await?;
+        if name == tag.name{
+            return Err(Error::Protocol("Invalid alias".to_string()));
+        }
+        Ok((name, tag))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Alias, AliasNew};
+    use crate::models::{link::{Link, LinkSearch}, tag::Tag,
+        test_utils::{setup, new_link}};
+
+    fn new_alias(alias: &str, tag: &str) -> AliasNew{
+        AliasNew{ alias: alias.to_string(), tag: tag.to_string() }
+    }
+
+    #[tokio::test]
+    async fn resolve_and_fold(){
+        let pool = setup().await;
+        Link::create_from_post(&pool, &new_link("https://example.com/1", &["js"]))
+            .await.unwrap();
+        Alias::create(&pool, &new_alias("js", "javascript")).await.unwrap();
+        Alias::create(&pool, &new_alias("JavaScript", "javascript")).await.unwrap();
+        let link = Link::create_from_post(&pool,
+            &new_link("https://example.com/2", &["JAVASCRIPT"])).await.unwrap();
+        assert_eq!(link.tags, vec!["javascript".to_string()]);
+        let search = LinkSearch{
+            searchtags: Some("js".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(Link::search(&pool, &search).await.unwrap().page.total, 2);
+        assert_eq!(Alias::fold(&pool).await.unwrap(), 1);
+        assert!(Tag::read_from_name(&pool, "js").await.is_err());
+        assert_eq!(Tag::read(&pool, "javascript").await.unwrap().occurrences, 2);
+        assert_eq!(Alias::read_all(&pool).await.unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,259 @@
+use serde::{Serialize, Deserialize};
+use chrono::Utc;
+use actix_web::web;
+use futures_util::{stream, StreamExt};
+use sqlx::{sqlite::{SqlitePool, Sqlite}, Transaction, Error, query};
+use log::debug;
+
+use super::{link::{Link, LinkSearch}, metatag::Metatag, revision::Revision,
+    alias::Alias, tag::Tag, normalization};
+
+/// Links fetched at the same time when refreshing metadata
+const CONCURRENT_FETCHES: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    AddTags,
+    RemoveTags,
+    SetPrivate,
+    SetPublic,
+    Delete,
+    Refresh,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Bulk {
+    pub ids: Option<Vec<i64>>,
+    pub query: Option<LinkSearch>,
+    pub operation: Operation,
+    pub tags: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BulkItem {
+    pub id: i64,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BulkReport {
+    pub operation: Operation,
+    pub total: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub items: Vec<BulkItem>,
+}
+
+impl BulkItem{
+    fn ok(id: i64) -> Self{
+        Self{ id, success: true, message: None }
+    }
+
+    fn error(id: i64, message: &str) -> Self{
+        Self{ id, success: false, message: Some(message.to_string()) }
+    }
+}
+
+impl Bulk{
+    /// Ids of the links selected by `ids` or, if not given, by `query`
+    async fn resolve(&self, pool: &web::Data<SqlitePool>)
+            -> Result<Vec<i64>, Error>{
+        if let Some(ids) = &self.ids{
+            return Ok(ids.to_owned());
+        }
+        match &self.query{
+            Some(search) => {
+                let search = LinkSearch{
+                    offset: None,
+                    limit: Some("all".to_string()),
+                    after: None,
+                    facets: None,
+                    ..search.clone()
+                };
+                let page = Link::search(pool, &search).await?.page;
+                Ok(page.items.iter().map(|link| link.id).collect())
+            },
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Run the operation over every selected link in a single transaction
+    pub async fn execute(&self, pool: &web::Data<SqlitePool>)
+            -> Result<BulkReport, Error>{
+        let ids = self.resolve(pool).await?;
+        debug!("Bulk {:?} over {} links", self.operation, ids.len());
+        let names = self.tags.to_owned().unwrap_or_default();
+        let mut tags = normalization::normalize_all(&names);
+        // Tags stored before the normalization are only found by their names
+        if self.operation == Operation::RemoveTags{
+            for name in names.iter().map(|name| name.trim()){
+                if !name.is_empty() && !tags.iter().any(|tag| tag == name){
+                    tags.push(name.to_string());
+                }
+            }
+        }
+        // Pages are read before opening the transaction to keep it short
+        let metatags = if self.operation == Operation::Refresh{
+            Self::fetch(pool, &ids).await
+        }else{
+            Vec::new()
+        };
+        let mut tx = pool.begin().await?;
+        let mut items = Vec::new();
+        for id in ids.iter(){
+            let item = match self.operation{
+                Operation::AddTags | Operation::RemoveTags if tags.is_empty() =>
+                    BulkItem::error(*id, "No tags given"),
+                Operation::Refresh => match metatags.iter()
+                        .find(|(link_id, _)| link_id == id){
+                    Some((_, Some(metatag))) => Self::apply(&mut tx, *id,
+                        self.operation, &tags, Some(metatag)).await?,
+                    Some((_, None)) => BulkItem::error(*id, "Can not read page"),
+                    None => BulkItem::error(*id, "Not found"),
+                },
+                _ => Self::apply(&mut tx, *id, self.operation, &tags, None)
+                    .await?,
+            };
+            items.push(item);
+        }
+        if self.operation == Operation::RemoveTags{
+            Tag::collect_garbage(&mut tx).await?;
+        }
+        tx.commit().await?;
+        let succeeded = items.iter().filter(|item| item.success).count();
+        Ok(BulkReport{
+            operation: self.operation,
+            total: items.len(),
+            succeeded,
+            failed: items.len() - succeeded,
+            items,
+        })
+    }
+
+    async fn fetch(pool: &web::Data<SqlitePool>, ids: &[i64])
+            -> Vec<(i64, Option<Metatag>)>{
+        stream::iter(ids.iter().copied())
+            .map(|id| async move {
+                match Link::read(pool, id).await{
+                    Ok(link) => Some((id, Metatag::new(&link.url).await)),
+                    Err(_) => None,
+                }
+            })
+            .buffer_unordered(CONCURRENT_FETCHES)
+            .filter_map(|item| async move { item })
+            .collect()
+            .await
+    }
+
+    async fn apply(tx: &mut Transaction<'_, Sqlite>, id: i64,
+            operation: Operation, tags: &[String], metatag: Option<&Metatag>)
+            -> Result<BulkItem, Error>{
+        match Revision::snapshot_with(&mut *tx, id).await{
+            Ok(_) => {},
+            Err(Error::RowNotFound) => return Ok(BulkItem::error(id, "Not found")),
+            Err(e) => return Err(e),
+        }
+        match operation{
+            Operation::AddTags => {
+                for tag in tags{
+                    let tag = match Alias::resolve(&mut *tx, tag).await?{
+                        Some(tag) => tag.name,
+                        None => tag.to_string(),
+                    };
+                    let sql = "INSERT INTO tags (name) VALUES ($1)
+                               ON CONFLICT(name) DO NOTHING";
+                    query(sql).bind(&tag).execute(&mut *tx).await?;
+                    let sql = "INSERT OR IGNORE INTO links_tags (link_id, tag_id)
+                               SELECT $1, id FROM tags WHERE name = $2";
+                    query(sql).bind(id).bind(&tag).execute(&mut *tx).await?;
+                }
+            },
+            Operation::RemoveTags => {
+                for tag in tags{
+                    let sql = "DELETE FROM links_tags WHERE link_id = $1
+                               AND tag_id IN (SELECT id FROM tags WHERE name = $2)";
+                    query(sql).bind(id).bind(tag).execute(&mut *tx).await?;
+                }
+            },
+            Operation::SetPrivate | Operation::SetPublic => {
+                let sql = "UPDATE links SET private = $1 WHERE id = $2";
+                query(sql)
+                    .bind(operation == Operation::SetPrivate)
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+            },
+            Operation::Delete => {
+                let sql = "UPDATE links SET deleted_at = $1 WHERE id = $2";
+                query(sql).bind(Utc::now()).bind(id).execute(&mut *tx).await?;
+                return Ok(BulkItem::ok(id));
+            },
+            Operation::Refresh => {
+                if let Some(metatag) = metatag{
+                    let sql = "UPDATE links SET title = $1, description = $2
+                               WHERE id = $3";
+                    query(sql)
+                        .bind(&metatag.title)
+                        .bind(&metatag.description)
+                        .bind(id)
+                        .execute(&mut *tx)
+                        .await?;
+                }
+            },
+        }
+        let sql = "UPDATE links SET updated = $1 WHERE id = $2";
+        query(sql).bind(Utc::now()).bind(id).execute(&mut *tx).await?;
+        Ok(BulkItem::ok(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bulk, Operation};
+    use crate::models::{link::Link, test_utils::{setup, new_link}};
+
+    #[tokio::test]
+    async fn add_tags(){
+        let pool = setup().await;
+        let first = Link::create_from_post(&pool, &new_link("https://example.com/1", &[]))
+            .await.unwrap();
+        let second = Link::create_from_post(&pool, &new_link("https://example.com/2", &[]))
+            .await.unwrap();
+        let bulk = Bulk{
+            ids: Some(vec![first.id, second.id, -1]),
+            query: None,
+            operation: Operation::AddTags,
+            tags: Some(vec!["bulk".to_string()]),
+        };
+        let report = bulk.execute(&pool).await.unwrap();
+        assert_eq!(report.succeeded, 2);
+        assert_eq!(report.failed, 1);
+        let link = Link::read(&pool, second.id).await.unwrap();
+        assert_eq!(link.tags, vec!["bulk".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn remove_legacy_tags(){
+        let pool = setup().await;
+        let link = Link::create_from_post(&pool, &new_link("https://example.com/1",
+            &["rust"])).await.unwrap();
+        // Stored as it came, before the names were normalized
+        sqlx::query("INSERT INTO tags (name) VALUES ('Rust Lang')")
+            .execute(pool.get_ref()).await.unwrap();
+        sqlx::query("INSERT INTO links_tags (link_id, tag_id)
+                     SELECT $1, id FROM tags WHERE name = 'Rust Lang'")
+            .bind(link.id)
+            .execute(pool.get_ref()).await.unwrap();
+        let bulk = Bulk{
+            ids: Some(vec![link.id]),
+            query: None,
+            operation: Operation::RemoveTags,
+            tags: Some(vec!["Rust Lang".to_string(), "rust".to_string()]),
+        };
+        assert_eq!(bulk.execute(&pool).await.unwrap().succeeded, 1);
+        assert_eq!(Link::read(&pool, link.id).await.unwrap().tags, Vec::<String>::new());
+    }
+}
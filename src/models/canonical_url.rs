@@ -0,0 +1,63 @@
+/// # Canonical URL
+/// Reduce the different spellings of the same address to a single one, so
+/// duplicated links can be detected.
+///
+/// * scheme and host in lowercase
+/// * without default ports, fragments or tracking parameters
+/// * without trailing slash in the path
+/// * with the query parameters sorted
+///
+/// ## Example
+/// * HTTPS://X.com:443/a/?utm_source=feed&b=2&a=1#top <=> https://x.com/a?a=1&b=2
+use reqwest::Url;
+
+static TRACKING_PARAMS: &[&str] = &["fbclid", "gclid", "dclid", "msclkid",
+    "yclid", "igshid", "mc_cid", "mc_eid", "_ga", "_hsenc", "_hsmi",
+    "ref_src", "ref_url"];
+static TRACKING_PREFIXES: &[&str] = &["utm_", "pk_", "mtm_"];
+
+pub fn canonicalize(url: &str) -> String {
+    let url = url.trim();
+    let mut parsed = match Url::parse(url){
+        Ok(parsed) => parsed,
+        Err(_) => return url.to_string(),
+    };
+    if parsed.cannot_be_a_base(){
+        return parsed.to_string();
+    }
+    parsed.set_fragment(None);
+    let mut params: Vec<(String, String)> = parsed.query_pairs()
+        .filter(|(key, _)| !is_tracking(key))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+    params.sort();
+    if params.is_empty(){
+        parsed.set_query(None);
+    }else{
+        parsed.query_pairs_mut()
+            .clear()
+            .extend_pairs(params);
+    }
+    let path = parsed.path().to_string();
+    if path.len() > 1 && path.ends_with('/'){
+        parsed.set_path(path.trim_end_matches('/'));
+    }
+    parsed.to_string()
+}
+
+fn is_tracking(key: &str) -> bool {
+    let key = key.to_lowercase();
+    TRACKING_PARAMS.contains(&key.as_str()) ||
+        TRACKING_PREFIXES.iter().any(|prefix| key.starts_with(prefix))
+}
+
+#[test]
+fn test_canonicalize(){
+    assert_eq!(canonicalize("HTTPS://X.com:443/a/?utm_source=feed&b=2&a=1#top"),
+        "https://x.com/a?a=1&b=2");
+    assert_eq!(canonicalize("https://x.com/a?utm_source=feed"),
+        canonicalize("https://x.com/a/"));
+    assert_eq!(canonicalize("http://x.com:80"), "http://x.com/");
+    assert_eq!(canonicalize("http://x.com:8080/"), "http://x.com:8080/");
+    assert_eq!(canonicalize("not an url"), "not an url");
+}
@@ -1,17 +1,31 @@
 use core::fmt;
+use std::{env, sync::OnceLock};
 
 use serde::{Serialize, Deserialize};
-use chrono::{DateTime, Utc};
+use serde_json::Value;
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
 use actix_web::web;
 use sqlx::{sqlite::{SqlitePool, SqliteRow}, Error, query, Row};
 use log::{error, debug};
+use tokio::sync::{broadcast, Mutex};
 
+use super::page::{self, Page, Order, Bind, Kind};
 
+/// Events kept in memory for the subscribers that fall behind
+const CHANNEL_CAPACITY: usize = 256;
+
+static CHANNEL: OnceLock<broadcast::Sender<History>> = OnceLock::new();
+/// Held while an event is written and sent, so the subscribers get them in
+/// the order of their ids, as the ones catching up rely on
+static WRITING: Mutex<()> = Mutex::const_new(());
+
+#[allow(clippy::upper_case_acronyms)]
 enum Event{
     CREATED,
     UPDATED,
     DELETED,
     SETTINGS,
+    TAG,
 }
 
 impl fmt::Display for Event{
@@ -21,16 +35,79 @@ impl fmt::Display for Event{
             Event::UPDATED => write!(f, "UPDATED"),
             Event::DELETED => write!(f, "DELETED"),
             Event::SETTINGS => write!(f, "SETTINGS"),
+            Event::TAG => write!(f, "TAG"),
         }
     }
 }
 
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Parameters of the search of events
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct HistorySearch {
+    pub since: Option<String>,
+    pub offset: Option<i32>,
+    pub limit: Option<String>,
+    pub order: Option<String>,
+    pub after: Option<String>,
+    /// Types of the events separated by commas, as `CREATED,DELETED`
+    pub event: Option<String>,
+}
+
+/// A page of events. With `expired` the events just after `since` are no
+/// longer kept, so some changes are missing and the client has to read
+/// everything again.
+#[derive(Debug)]
+pub struct HistoryPage {
+    pub page: Page<History>,
+    pub expired: bool,
+}
+
+/// How long the events are kept, configured with env vars:
+/// * `HISTORY_MAX_DAYS`: days an event is kept, `0` for ever (default `0`)
+/// * `HISTORY_MAX_ROWS`: events kept, `0` for all of them (default `0`)
+/// * `HISTORY_COMPACT_MINUTES`: the UPDATED events of a link closer than
+///   these minutes are compacted into the last one, `0` to keep them all
+///   (default `0`)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Retention {
+    pub max_days: i64,
+    pub max_rows: i64,
+    pub compact_minutes: i64,
+}
+
+impl Retention{
+    pub fn from_env() -> Self{
+        let default = Self::default();
+        Self{
+            max_days: number("HISTORY_MAX_DAYS", default.max_days),
+            max_rows: number("HISTORY_MAX_ROWS", default.max_rows),
+            compact_minutes: number("HISTORY_COMPACT_MINUTES",
+                default.compact_minutes),
+        }
+    }
+
+    pub fn enabled(&self) -> bool{
+        self.max_days > 0 || self.max_rows > 0 || self.compact_minutes > 0
+    }
+}
+
+fn number(key: &str, default: i64) -> i64{
+    env::var(key)
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .filter(|v| *v >= 0)
+        .unwrap_or(default)
+}
+
+/// An event with the link it happened to, if any, and the details of the
+/// change in `payload`
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct History {
     pub id: i64,
     pub event: String,
     pub datetime: DateTime<Utc>,
+    pub link_id: Option<i64>,
+    pub payload: Option<Value>,
 }
 
 impl History{
@@ -39,64 +116,355 @@ impl History{
             id: row.get("id"),
             event: row.get("event"),
             datetime: row.get("dt"),
+            link_id: row.get("link_id"),
+            payload: row.get::<Option<String>, &str>("payload")
+                .and_then(|payload| serde_json::from_str(&payload).ok()),
         }
     }
 
-    pub async fn created(pool: &web::Data<SqlitePool>){
-        Self::insert(pool, Event::CREATED).await;
+    pub async fn created(pool: &web::Data<SqlitePool>, link_id: i64){
+        Self::insert(pool, Event::CREATED, Some(link_id), None).await;
     }
 
-    pub async fn updated(pool: &web::Data<SqlitePool>){
-        Self::insert(pool, Event::UPDATED).await;
+    pub async fn updated(pool: &web::Data<SqlitePool>, link_id: i64){
+        Self::insert(pool, Event::UPDATED, Some(link_id), None).await;
     }
 
-    pub async fn deleted(pool: &web::Data<SqlitePool>){
-        Self::insert(pool, Event::DELETED).await;
+    pub async fn deleted(pool: &web::Data<SqlitePool>, link_id: i64){
+        Self::insert(pool, Event::DELETED, Some(link_id), None).await;
     }
 
     pub async fn settings(pool: &web::Data<SqlitePool>){
-        Self::insert(pool, Event::SETTINGS).await;
+        Self::insert(pool, Event::SETTINGS, None, None).await;
+    }
+
+    /// Change of a tag, as its rename or deletion, described in `payload`
+    pub async fn tag(pool: &web::Data<SqlitePool>, payload: Value){
+        Self::insert(pool, Event::TAG, None, Some(payload)).await;
     }
 
-    async fn insert(pool: &web::Data<SqlitePool>, event: Event){
+    /// Same as `created` with the details of the change
+    pub async fn created_with(pool: &web::Data<SqlitePool>, link_id: i64,
+            payload: Value){
+        Self::insert(pool, Event::CREATED, Some(link_id), Some(payload)).await;
+    }
+
+    pub async fn updated_with(pool: &web::Data<SqlitePool>, link_id: i64,
+            payload: Value){
+        Self::insert(pool, Event::UPDATED, Some(link_id), Some(payload)).await;
+    }
+
+    pub async fn deleted_with(pool: &web::Data<SqlitePool>, link_id: i64,
+            payload: Value){
+        Self::insert(pool, Event::DELETED, Some(link_id), Some(payload)).await;
+    }
+
+    async fn insert(pool: &web::Data<SqlitePool>, event: Event,
+            link_id: Option<i64>, payload: Option<Value>){
         debug!("insert in the history");
         let datetime = Utc::now();
         debug!("Datetime: {}", datetime);
-        let sql = "INSERT INTO history (event, dt) VALUES ($1, $2);";
+        let sql = "INSERT INTO history (event, dt, link_id, payload)
+                   VALUES ($1, $2, $3, $4) RETURNING *;";
         debug!("Sql: {}", sql);
         debug!("Sql: {}", event);
+        let _writing = WRITING.lock().await;
         match query(sql)
             .bind(event.to_string())
             .bind(datetime)
-            .execute(pool.get_ref())
+            .bind(link_id)
+            .bind(payload.map(|payload| payload.to_string()))
+            .map(Self::from_row)
+            .fetch_one(pool.get_ref())
             .await{
-                Ok(_) => debug!("Event created: {}", event),
+                Ok(history) => {
+                    debug!("Event created: {}", event);
+                    // Nobody listening is not an error
+                    let _ = Self::channel().send(history);
+                },
                 Err(e) => error!("Can not write history: {}", e),
             }
     }
 
-    pub async fn search(pool: &web::Data<SqlitePool>, since: &Option<String>, option_offset: &Option<i32>, option_limit: &Option<String>) -> Result<Vec<History>, Error>{
-        let offset = option_offset.unwrap_or(0);
-        let limit = match option_limit {
-            Some(v) => v.to_owned(),
-            None => "20".to_string(),
-        };
-        let mut sql = Vec::new();
-        sql.push("SELECT * FROM history".to_string());
-        sql.push(match since{
-            Some(v) => format!("WHERE dt > '{}'", v),
-            None => "".to_string(),
-        });
-        sql.push("ORDER BY dt".to_string());
-        sql.push(if limit != "all"{
-            format!("LIMIT {} OFFSET {}", limit, offset)
-        }else{
-            "".to_string()
-        });
-        debug!("{}", &sql.join(" "));
-        query(&sql.join(" "))
+    fn channel() -> &'static broadcast::Sender<History>{
+        CHANNEL.get_or_init(|| broadcast::channel(CHANNEL_CAPACITY).0)
+    }
+
+    /// Receiver of every event from now on, as it is written
+    pub fn subscribe() -> broadcast::Receiver<History>{
+        Self::channel().subscribe()
+    }
+
+    /// Events written after the one with id `last_id`, to resume a stream
+    pub async fn read_after(pool: &web::Data<SqlitePool>, last_id: i64)
+            -> Result<Vec<History>, Error>{
+        let sql = "SELECT * FROM history WHERE id > $1 ORDER BY id";
+        query(sql)
+            .bind(last_id)
             .map(Self::from_row)
             .fetch_all(pool.get_ref())
             .await
     }
+
+    /// `since` as a date, in RFC 3339 or as `%Y-%m-%d %H:%M:%S` in UTC, to
+    /// compare it with the stored dates
+    fn parse_since(since: &str) -> Result<DateTime<Utc>, Error>{
+        if let Ok(datetime) = DateTime::parse_from_rfc3339(since){
+            return Ok(datetime.with_timezone(&Utc));
+        }
+        NaiveDateTime::parse_from_str(since, "%Y-%m-%d %H:%M:%S")
+            .map(|datetime| DateTime::from_naive_utc_and_offset(datetime, Utc))
+            .map_err(|_| Error::Protocol("Invalid since".to_string()))
+    }
+
+    /// Events after `since`, of the types in `event` if given, sorted by date in pages of `limit` events. The
+    /// next page starts after the cursor `after` or, without it, after
+    /// `offset` events.
+    pub async fn search(pool: &web::Data<SqlitePool>, search: &HistorySearch)
+            -> Result<HistoryPage, Error>{
+        let order = Order::from_param(&search.order);
+        let limit = page::limit(&search.limit);
+        let mut conditions = vec!["1 = 1".to_string()];
+        let mut binds = Vec::new();
+        let horizon = Self::horizon(pool).await?;
+        // Without `since` every event is asked for
+        let mut expired = horizon.is_some();
+        if let Some(since) = &search.since{
+            let since = Self::parse_since(since)?;
+            expired = match &horizon{
+                Some((_, last_dt)) => &since < last_dt,
+                None => false,
+            };
+            conditions.push("dt > ?".to_string());
+            binds.push(Bind::Datetime(since));
+        }
+        if let Some(event) = &search.event{
+            let events: Vec<String> = event.split(',')
+                .map(|event| event.trim().to_uppercase())
+                .filter(|event| !event.is_empty())
+                .collect();
+            if !events.is_empty(){
+                conditions.push(format!("event IN ({})",
+                    vec!["?"; events.len()].join(",")));
+                binds.extend(events.into_iter().map(Bind::Text));
+            }
+        }
+        let sql = format!("SELECT count(*) FROM history WHERE {}",
+            conditions.join(" AND "));
+        let total: i64 = page::bind_all(query(&sql), &binds)
+            .map(|row: SqliteRow| row.get(0))
+            .fetch_one(pool.get_ref())
+            .await?;
+        let columns = [("dt", order), ("id", order)];
+        let mut offset = search.offset.unwrap_or(0).into();
+        if let Some(after) = &search.after{
+            let values = page::read_cursor(after, &[Kind::Datetime, Kind::Int])
+                .ok_or_else(|| Error::Protocol("Invalid cursor".to_string()))?;
+            let (condition, after_binds) = page::after_condition(&columns,
+                &values);
+            conditions.push(condition);
+            binds.extend(after_binds);
+            offset = 0;
+        }
+        let sql = format!("SELECT * FROM history WHERE {} ORDER BY dt {}, id {}
+                           LIMIT ? OFFSET ?", conditions.join(" AND "),
+                           order.sql(), order.sql());
+        binds.push(Bind::Int(limit.unwrap_or(-1)));
+        binds.push(Bind::Int(offset));
+        debug!("{}", &sql);
+        let items = page::bind_all(query(&sql), &binds)
+            .map(Self::from_row)
+            .fetch_all(pool.get_ref())
+            .await?;
+        let next = match (items.last(), limit){
+            (Some(last), Some(limit)) if items.len() as i64 == limit =>
+                Some(page::encode_cursor(&[
+                    Bind::Datetime(last.datetime).to_value(),
+                    Bind::Int(last.id).to_value()])),
+            _ => None,
+        };
+        Ok(HistoryPage{ page: Page{ items, total, next }, expired })
+    }
+
+    /// Id and date of the last event removed by the retention, if any.
+    /// The events up to it are no longer kept.
+    pub async fn horizon(pool: &web::Data<SqlitePool>)
+            -> Result<Option<(i64, DateTime<Utc>)>, Error>{
+        let sql = "SELECT last_id, last_dt FROM history_horizon WHERE id = 1";
+        query(sql)
+            .map(|row: SqliteRow| (row.get("last_id"), row.get("last_dt")))
+            .fetch_optional(pool.get_ref())
+            .await
+    }
+
+    /// Remove the UPDATED events of a link followed by another one of the
+    /// same link within `minutes`, so a burst of edits leaves one event.
+    /// Only events without payload, or with the same as the next one, are
+    /// removed, so no detail of a change is lost.
+    pub async fn compact(pool: &web::Data<SqlitePool>, minutes: i64)
+            -> Result<u64, Error>{
+        let sql = "DELETE FROM history WHERE event = 'UPDATED'
+                   AND link_id IS NOT NULL AND EXISTS (
+                       SELECT 1 FROM history h
+                       WHERE h.link_id = history.link_id
+                       AND h.event = 'UPDATED' AND h.id > history.id
+                       AND (history.payload IS NULL
+                           OR history.payload = h.payload)
+                       AND julianday(h.dt) - julianday(history.dt)
+                           <= $1 / 1440.0)";
+        Ok(query(sql)
+            .bind(minutes)
+            .execute(pool.get_ref())
+            .await?
+            .rows_affected())
+    }
+
+    /// Remove the events older than `max_days` and the ones beyond the
+    /// newest `max_rows`, moving the horizon to the last one removed
+    pub async fn prune(pool: &web::Data<SqlitePool>, max_days: i64,
+            max_rows: i64) -> Result<u64, Error>{
+        let mut tx = pool.begin().await?;
+        let mut last_id: Option<i64> = None;
+        if max_days > 0{
+            let sql = "SELECT max(id) FROM history WHERE dt < $1";
+            last_id = query(sql)
+                .bind(Utc::now() - Duration::days(max_days))
+                .map(|row: SqliteRow| row.get(0))
+                .fetch_one(&mut tx)
+                .await?;
+        }
+        if max_rows > 0{
+            let sql = "SELECT id FROM history ORDER BY id DESC
+                       LIMIT 1 OFFSET $1";
+            let beyond: Option<i64> = query(sql)
+                .bind(max_rows)
+                .map(|row: SqliteRow| row.get(0))
+                .fetch_optional(&mut tx)
+                .await?;
+            last_id = last_id.max(beyond);
+        }
+        let last_id = match last_id{
+            Some(last_id) => last_id,
+            None => return Ok(0),
+        };
+        let sql = "INSERT INTO history_horizon (id, last_id, last_dt)
+                   SELECT 1, id, dt FROM history WHERE id = $1
+                   ON CONFLICT(id) DO UPDATE SET last_id = excluded.last_id,
+                   last_dt = excluded.last_dt";
+        query(sql)
+            .bind(last_id)
+            .execute(&mut tx)
+            .await?;
+        let sql = "DELETE FROM history WHERE id <= $1";
+        let pruned = query(sql)
+            .bind(last_id)
+            .execute(&mut tx)
+            .await?
+            .rows_affected();
+        tx.commit().await?;
+        Ok(pruned)
+    }
+
+    /// Compact and prune the history as configured
+    pub async fn retain(pool: &web::Data<SqlitePool>, retention: &Retention)
+            -> Result<u64, Error>{
+        let mut removed = 0;
+        if retention.compact_minutes > 0{
+            removed += Self::compact(pool, retention.compact_minutes).await?;
+        }
+        removed += Self::prune(pool, retention.max_days, retention.max_rows)
+            .await?;
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::test_utils::setup;
+    use serde_json::json;
+    use super::{History, HistorySearch, Retention};
+
+    #[tokio::test]
+    async fn search_by_event(){
+        let pool = setup().await;
+        History::created(&pool, 1).await;
+        History::updated_with(&pool, 1, json!({"sticky": true})).await;
+        History::deleted(&pool, 2).await;
+        let search = HistorySearch{
+            event: Some("updated,deleted".to_string()),
+            ..Default::default()
+        };
+        let page = History::search(&pool, &search).await.unwrap().page;
+        assert_eq!(page.total, 2);
+        assert_eq!(page.items[0].link_id, Some(1));
+        assert_eq!(page.items[0].payload, Some(json!({"sticky": true})));
+        assert_eq!(page.items[1].event, "DELETED");
+    }
+
+    #[tokio::test]
+    async fn subscribe_and_resume(){
+        let pool = setup().await;
+        let mut receiver = History::subscribe();
+        // The channel is shared with the tests running alongside, so only the
+        // events carrying this test's marker are looked at
+        let marker = json!({"test": "subscribe_and_resume"});
+        History::created_with(&pool, 1, marker.clone()).await;
+        History::tag(&pool, json!({"tag": "rust", "deleted": true})).await;
+        let mut first = receiver.recv().await.unwrap();
+        while first.payload.as_ref() != Some(&marker){
+            first = receiver.recv().await.unwrap();
+        }
+        assert_eq!(first.event, "CREATED");
+        assert_eq!(first.link_id, Some(1));
+        let after = History::read_after(&pool, first.id).await.unwrap();
+        assert_eq!(after.len(), 1);
+        assert_eq!(after[0].payload, Some(json!({"tag": "rust", "deleted": true})));
+    }
+
+    #[tokio::test]
+    async fn compact_and_prune(){
+        let pool = setup().await;
+        History::created(&pool, 1).await;
+        for _ in 0..3{
+            History::updated(&pool, 1).await;
+        }
+        History::updated(&pool, 2).await;
+        let retention = Retention{ max_days: 0, max_rows: 2, compact_minutes: 5 };
+        let search = HistorySearch{
+            limit: Some("all".to_string()),
+            ..Default::default()
+        };
+        let before = History::search(&pool, &search).await.unwrap();
+        assert!(!before.expired);
+        // Two of the three updates of the link 1, and then the creation
+        assert_eq!(History::retain(&pool, &retention).await.unwrap(), 3);
+        let after = History::search(&pool, &search).await.unwrap();
+        assert!(after.expired);
+        assert_eq!(after.page.total, 2);
+        assert_eq!(after.page.items[0].link_id, Some(1));
+        assert_eq!(after.page.items[0].event, "UPDATED");
+        let (last_id, last_dt) = History::horizon(&pool).await.unwrap().unwrap();
+        assert_eq!(last_id, before.page.items[0].id);
+        let search = HistorySearch{
+            since: Some(last_dt.to_rfc3339()),
+            ..Default::default()
+        };
+        assert!(!History::search(&pool, &search).await.unwrap().expired);
+        let search = HistorySearch{
+            since: Some("yesterday".to_string()),
+            ..Default::default()
+        };
+        assert!(History::search(&pool, &search).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn compact_keeps_payloads(){
+        let pool = setup().await;
+        History::updated_with(&pool, 1, json!({"sync": true})).await;
+        History::updated_with(&pool, 1, json!({"sync": true})).await;
+        History::updated_with(&pool, 1, json!({"bulk": "refresh"})).await;
+        History::updated(&pool, 1).await;
+        // Only the first, the same as the second
+        assert_eq!(History::compact(&pool, 5).await.unwrap(), 1);
+    }
 }
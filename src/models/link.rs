@@ -1,48 +1,90 @@
 use serde::{Serialize, Deserialize};
-use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use chrono::{DateTime, Duration, Utc};
 use actix_web::web;
-use sqlx::{sqlite::{SqlitePool, SqliteRow, SqliteQueryResult}, Error, query, Row};
-use log::debug;
+use sqlx::{sqlite::{SqlitePool, SqliteRow, SqliteQueryResult, Sqlite},
+    Transaction, Error, query, Row};
+use log::{debug, info, error};
 
 
-use super::{metatag::Metatag, short_url, tag::Tag, link_tag::LinkTag};
+use super::{metatag::Metatag, short_url, tag::{self, Tag, TagCooccurrence},
+    link_tag::LinkTag, alias::Alias, suggestion::Suggestion, canonical_url, revision::Revision,
+    page::{self, Page, Order, Bind, Kind}, normalization, history::History};
 
 
+/// Notes have no external url, they point to their own permalink
+pub const NOTE_PREFIX: &str = "/shaare/";
+const SHORTURL_ATTEMPTS: u32 = 10;
 
 #[derive(Debug, Serialize, Deserialize, Eq)]
 pub struct Link {
     pub id: i64,
     pub url: String,
+    pub url_canonical: String,
     pub shorturl: String,
     pub title: String,
     pub description: String,
     pub private: bool,
+    pub sticky: bool,
     pub created: DateTime<Utc>,
     pub updated: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LinkWithTags {
-    id: i64,
-    url: String,
-    shorturl: String,
-    title: String,
-    description: String,
-    tags: Vec<String>,
-    private: bool,
-    created: DateTime<Utc>,
-    updated: DateTime<Utc>,
+    pub id: i64,
+    pub url: String,
+    pub shorturl: String,
+    pub title: String,
+    pub description: String,
+    pub tags: Vec<String>,
+    pub private: bool,
+    pub sticky: bool,
+    pub created: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LinkWithTagsNew {
-    url: String,
-    title: Option<String>,
-    description: Option<String>,
-    tags: Option<Vec<String>>,
-    private: Option<bool>,
-    created: Option<DateTime<Utc>>,
-    updated: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub url: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub tags: Option<Vec<String>>,
+    pub private: Option<bool>,
+    pub sticky: Option<bool>,
+    pub created: Option<DateTime<Utc>>,
+    pub updated: Option<DateTime<Utc>>,
+}
+
+/// Parameters of the search of links
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct LinkSearch {
+    pub offset: Option<i32>,
+    pub limit: Option<String>,
+    pub searchterm: Option<String>,
+    pub searchtags: Option<String>,
+    /// Also match the descendants of `searchtags`, as `lang:rust` for `lang`
+    pub descendants: Option<bool>,
+    pub visibility: Option<String>,
+    #[serde(rename = "type")]
+    pub link_type: Option<String>,
+    pub sort: Option<String>,
+    pub order: Option<String>,
+    pub after: Option<String>,
+    /// Also count the tags of every link matching the search
+    pub facets: Option<bool>,
+}
+
+/// A page of links, with the tags of all the links matching the search, not
+/// only the ones in the page, if asked for
+#[derive(Debug)]
+pub struct LinkPage {
+    pub page: Page<LinkWithTags>,
+    pub facets: Option<Vec<TagCooccurrence>>,
 }
 
 impl PartialEq for Link{
@@ -56,20 +98,25 @@ impl Link{
         Link{
             id: row.get("id"),
             url: row.get("url"),
+            url_canonical: row.get("url_canonical"),
             shorturl: row.get("shorturl"),
             title: row.get("title"),
             description: row.get("description"),
             private: row.get("private"),
+            sticky: row.get("sticky"),
             created: row.get("created"),
             updated: row.get("updated"),
+            deleted_at: row.get("deleted_at"),
         }
     }
 
     fn from_row_with_tags(row: SqliteRow) -> LinkWithTags{
-        let tags = row.get::<String, &str>("tags")
-            .split(",")
-            .map(|item| item.to_string())
-            .collect();
+        let tags = match row.get::<Option<String>, &str>("tags"){
+            Some(tags) => tags.split(',')
+                .map(|item| item.to_string())
+                .collect(),
+            None => Vec::new(),
+        };
         LinkWithTags{
             id: row.get("id"),
             url: row.get("url"),
@@ -78,17 +125,29 @@ impl Link{
             tags,
             description: row.get("description"),
             private: row.get("private"),
+            sticky: row.get("sticky"),
             created: row.get("created"),
             updated: row.get("updated"),
+            deleted_at: row.get("deleted_at"),
         }
     }
 
     pub async fn create_from_post(pool: &web::Data<SqlitePool>, 
             link_with_tags: &LinkWithTagsNew) -> Result<LinkWithTags, Error>{
-        let url = &link_with_tags.url;
-        let metatag = match Metatag::new(&url).await{
-            Some(m) => m,
-            None => Metatag::empty(url),
+        let url = link_with_tags.url.trim();
+        let note = url.is_empty();
+        let metatag = if note {
+            // A note has no page to read from, its own text is the metadata
+            let mut metatag = Metatag::empty(url);
+            metatag.title = link_with_tags.title.to_owned().unwrap_or_default();
+            metatag.description = link_with_tags.description.to_owned()
+                .unwrap_or_default();
+            metatag
+        }else{
+            match Metatag::new(url).await{
+                Some(m) => m,
+                None => Metatag::empty(url),
+            }
         };
         let title = match &link_with_tags.title {
             Some(title) => title,
@@ -99,8 +158,11 @@ impl Link{
             None => &metatag.description,
         };
         let tags_names = match &link_with_tags.tags {
-            Some(tags) => tags,
-            None => &metatag.tags,
+            Some(tags) => tags.to_owned(),
+            None => match Suggestion::suggest(pool, url, &metatag).await{
+                Ok(suggestions) => Suggestion::confident(&suggestions),
+                Err(_) => Vec::new(),
+            },
         };
         let private = match link_with_tags.private{
             Some(private) => private,
@@ -114,48 +176,200 @@ impl Link{
             Some(updated) => updated,
             None => Utc::now(),
         };
-        let sql = "INSERT INTO links (url, shorturl, title, description,
-                   private, created, updated) VALUES ($1, $2, $3, $4, $5, $6,
-                   $7) RETURNING * ;";
+        // The shorturl, and the url of a note, depend on the id
+        let mut tx = pool.begin().await?;
+        let sql = "INSERT INTO links (url, url_canonical, shorturl, title,
+                   description, private, sticky, created, updated) VALUES ($1,
+                   $2, $3, $4, $5, $6, $7, $8, $9) RETURNING * ;";
         let link = query(sql)
             .bind(url)
+            .bind(canonical_url::canonicalize(url))
             .bind("")
             .bind(title)
             .bind(description)
             .bind(private)
+            .bind(link_with_tags.sticky.unwrap_or(false))
             .bind(created)
             .bind(updated)
             .map(Self::from_row)
-            .fetch_one(pool.get_ref())
+            .fetch_one(&mut tx)
+            .await?;
+        let shorturl = Self::generate_shorturl(&mut tx, &link).await?;
+        let url = if note {
+            format!("{}{}", NOTE_PREFIX, shorturl)
+        }else{
+            link.url
+        };
+        let sql = "UPDATE links SET url = $1, url_canonical = $2, shorturl = $3
+                   WHERE id = $4;";
+        query(sql)
+            .bind(&url)
+            .bind(canonical_url::canonicalize(&url))
+            .bind(&shorturl)
+            .bind(link.id)
+            .execute(&mut tx)
             .await?;
-        let shorturl = Self::set_shorturl(pool, link.id).await;
-        for tag_name in tags_names{
+        tx.commit().await?;
+        for tag_name in normalization::normalize_all(&tags_names).iter(){
             let tag = Tag::get_or_insert(&pool, &tag_name).await.unwrap();
             let _ = LinkTag::create(pool, link.id, tag.id).await;
         }
         let tags = Tag::read_tags_for_link(&pool, link.id).await.unwrap();
         Ok(LinkWithTags {
             id: link.id,
-            url: link.url,
+            url,
             shorturl,
             title: link.title,
             description: link.description,
             tags,
             private: link.private,
+            sticky: link.sticky,
             created: link.created,
             updated: link.updated,
+            deleted_at: None,
         })
     }
-    async fn set_shorturl(pool: &web::Data<SqlitePool>, id: i64) -> String{
-        let shorturl = short_url::encode(id.try_into().unwrap());
-        let sql = "UPDATE links SET shorturl = $1 WHERE id = $2;";
-        query(sql)
-            .bind(&shorturl)
-            .bind(id)
-            .execute(pool.get_ref())
-            .await;
-        shorturl
+    /// Generate a short url not used by any other link
+    async fn generate_shorturl(tx: &mut Transaction<'_, Sqlite>, link: &Link)
+            -> Result<String, Error>{
+        let strategy = short_url::strategy()
+            .map_err(|e| Error::Configuration(Box::new(e)))?;
+        // Notes have no url yet, their permalink depends on the id
+        let source = if link.url.is_empty(){
+            format!("{}{}", NOTE_PREFIX, link.id)
+        }else{
+            link.url.to_string()
+        };
+        let sql = "SELECT count(*) FROM links WHERE shorturl = $1 AND id != $2";
+        for attempt in 0..SHORTURL_ATTEMPTS{
+            let shorturl = strategy.generate(link.id, &source, &link.created,
+                    attempt)
+                .map_err(|e| Error::Protocol(e.to_string()))?;
+            let used: i64 = query(sql)
+                .bind(&shorturl)
+                .bind(link.id)
+                .map(|row: SqliteRow| row.get(0))
+                .fetch_one(&mut *tx)
+                .await?;
+            if used == 0 {
+                return Ok(shorturl);
+            }
+            debug!("Short url collision: {}", shorturl);
+        }
+        Err(Error::Protocol(format!("Can not generate a short url for {}",
+            link.url)))
     }
+
+    /// Fill the short url of the links that have none
+    pub async fn freeze_shorturls(pool: &web::Data<SqlitePool>){
+        let sql = "SELECT * FROM links WHERE shorturl = ''";
+        let links = match query(sql)
+            .map(Self::from_row)
+            .fetch_all(pool.get_ref())
+            .await{
+                Ok(links) => links,
+                Err(e) => {
+                    error!("Can not read links: {}", e);
+                    return;
+                },
+        };
+        let sql = "UPDATE links SET shorturl = $1 WHERE id = $2";
+        for link in links{
+            let result = match pool.begin().await{
+                Ok(mut tx) => match Self::generate_shorturl(&mut tx, &link).await{
+                    Ok(shorturl) => match query(sql)
+                        .bind(&shorturl)
+                        .bind(link.id)
+                        .execute(&mut tx)
+                        .await{
+                            Ok(_) => tx.commit().await,
+                            Err(e) => Err(e),
+                        },
+                    Err(e) => Err(e),
+                },
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result{
+                error!("Can not set short url for {}: {}", link.url, e);
+            }
+        }
+    }
+
+    /// Fill the canonical url of the links stored before it existed. A link
+    /// with the same canonical url as one already filled is a duplicate: its
+    /// tags are merged into that one and it is moved to the trash. Links in
+    /// the trash go last, so they never take the place of a live one.
+    pub async fn canonicalize_all(pool: &web::Data<SqlitePool>){
+        let sql = "SELECT * FROM links WHERE url_canonical = ''
+                   ORDER BY deleted_at IS NOT NULL, id";
+        let links = match query(sql)
+            .map(Self::from_row)
+            .fetch_all(pool.get_ref())
+            .await{
+                Ok(links) => links,
+                Err(e) => {
+                    error!("Can not read links: {}", e);
+                    return;
+                },
+        };
+        let sql = "UPDATE links SET url_canonical = $1 WHERE id = $2";
+        for link in links{
+            match query(sql)
+                .bind(canonical_url::canonicalize(&link.url))
+                .bind(link.id)
+                .execute(pool.get_ref())
+                .await{
+                    Ok(_) => {},
+                    Err(Error::Database(_)) => Self::merge_duplicate(pool, &link).await,
+                    Err(e) => error!("Can not canonicalize {}: {}", link.url, e),
+            }
+        }
+    }
+
+    /// Merge the link into the one with its canonical url, see `canonicalize_all`
+    async fn merge_duplicate(pool: &web::Data<SqlitePool>, link: &Link){
+        let existing = match Self::read_from_canonical(pool, &link.url).await{
+            Ok(existing) => existing,
+            Err(e) => {
+                error!("Can not canonicalize {}: {}", link.url, e);
+                return;
+            },
+        };
+        // Left as it is until it is purged
+        if link.deleted_at.is_some(){
+            debug!("Link {} in the trash duplicates {}", link.id, existing.id);
+            return;
+        }
+        if existing.deleted_at.is_some(){
+            error!("Can not canonicalize {}: duplicates {} in the trash",
+                link.url, existing.id);
+            return;
+        }
+        let result = match Self::read(pool, link.id).await{
+            Ok(duplicate) => Self::merge_tags(pool, existing.id, &duplicate.tags)
+                .await,
+            Err(e) => Err(e),
+        };
+        match result{
+            Ok(_) => {
+                History::updated_with(pool, existing.id, json!({"merged": true}))
+                    .await;
+            },
+            Err(e) => {
+                error!("Can not merge {} into {}: {}", link.id, existing.id, e);
+                return;
+            },
+        }
+        match Self::delete(pool, link.id).await{
+            Ok(_) => {
+                History::deleted(pool, link.id).await;
+                info!("Link {} merged into {}, the same url, and moved to the trash",
+                    link.id, existing.id);
+            },
+            Err(e) => error!("Can not delete the duplicate {}: {}", link.id, e),
+        }
+    }
+
     pub async fn create(pool: &web::Data<SqlitePool>, url: &str) 
             -> Result<LinkWithTags, Error>{
         let metatag = Metatag::new(&url).await.unwrap();
@@ -172,6 +386,7 @@ impl Link{
             description: Some(description),
             tags: Some(tags_names),
             private: Some(private),
+            sticky: None,
             created: Some(created),
             updated: Some(updated),
         };
@@ -191,7 +406,7 @@ impl Link{
                         GROUP BY lt.link_id)
                     SELECT l.*,t.tags FROM links l
                     LEFT JOIN mtags t ON l.id=t.link_id
-                    WHERE id = $1
+                    WHERE id = $1 AND l.deleted_at IS NULL
                     ORDER BY id";
         //let sql = "SELECT * FROM links WHERE id = $1;";
         query(sql)
@@ -201,6 +416,56 @@ impl Link{
             .await
     }
 
+    pub async fn read_from_shorturl(pool: &web::Data<SqlitePool>,
+            shorturl: &str) -> Result<LinkWithTags, Error>{
+        let sql = " WITH mtags AS (
+                        SELECT lt.link_id, group_concat(name) tags FROM tags t
+                        INNER JOIN links_tags lt ON t.id = lt.tag_id
+                        GROUP BY lt.link_id)
+                    SELECT l.*,t.tags FROM links l
+                    LEFT JOIN mtags t ON l.id=t.link_id
+                    WHERE shorturl = $1 AND l.deleted_at IS NULL";
+        query(sql)
+            .bind(shorturl)
+            .map(Self::from_row_with_tags)
+            .fetch_one(pool.get_ref())
+            .await
+    }
+
+    /// Links in the trash are also found, a new link can not take their url
+    pub async fn read_from_canonical(pool: &web::Data<SqlitePool>, url: &str)
+            -> Result<LinkWithTags, Error>{
+        let sql = " WITH mtags AS (
+                        SELECT lt.link_id, group_concat(name) tags FROM tags t
+                        INNER JOIN links_tags lt ON t.id = lt.tag_id
+                        GROUP BY lt.link_id)
+                    SELECT l.*,t.tags FROM links l
+                    LEFT JOIN mtags t ON l.id=t.link_id
+                    WHERE url_canonical = $1";
+        query(sql)
+            .bind(canonical_url::canonicalize(url))
+            .map(Self::from_row_with_tags)
+            .fetch_one(pool.get_ref())
+            .await
+    }
+
+    /// Add the tags of a duplicated post to the link that already exists
+    pub async fn merge_tags(pool: &web::Data<SqlitePool>, link_id: i64,
+            tags_names: &[String]) -> Result<LinkWithTags, Error>{
+        Revision::snapshot(pool, link_id).await?;
+        for tag_name in normalization::normalize_all(tags_names).iter(){
+            let tag = Tag::get_or_insert(pool, tag_name).await?;
+            let _ = LinkTag::create(pool, link_id, tag.id).await;
+        }
+        let sql = "UPDATE links SET updated = $1 WHERE id = $2";
+        query(sql)
+            .bind(Utc::now())
+            .bind(link_id)
+            .execute(pool.get_ref())
+            .await?;
+        Self::read(pool, link_id).await
+    }
+
     pub async fn read_all(pool: &web::Data<SqlitePool>) 
             -> Result<Vec<LinkWithTags>, Error>{
         let sql = " WITH mtags AS (
@@ -209,6 +474,7 @@ impl Link{
                         GROUP BY lt.link_id)
                     SELECT l.*,t.tags FROM links l
                     LEFT JOIN mtags t ON l.id=t.link_id
+                    WHERE l.deleted_at IS NULL
                     ORDER BY id";
         query(sql)
             .map(Self::from_row_with_tags)
@@ -216,106 +482,380 @@ impl Link{
             .await
     }
 
-    pub async fn search(pool: &web::Data<SqlitePool>, 
-            option_offset: &Option<i32>,
-            option_limit: &Option<String>,
-            option_searchterm: &Option<String>,
-            option_searchtags: &Option<String>,
-            option_visibility: &Option<String>,
-            ) -> Result<Vec<LinkWithTags>, Error>{
-        let offset = option_offset.unwrap_or(0);
-        let limit = match option_limit {
-            Some(v) => v.to_owned(),
-            None => "20".to_string(),
+    /// Conditions of the search shared by the listing and its total
+    fn filter(search: &LinkSearch) -> (Vec<String>, Vec<Bind>){
+        let mut conditions = vec!["l.deleted_at IS NULL".to_string()];
+        let mut binds = Vec::new();
+        if let Some(value) = &search.searchterm{
+            conditions.push("(l.title LIKE ? OR l.description LIKE ?)"
+                .to_string());
+            binds.push(Bind::Text(format!("%{}%", value)));
+            binds.push(Bind::Text(format!("%{}%", value)));
+        }
+        if let Some(value) = &search.searchtags{
+            let tags: Vec<String> = value.split(|c: char| c == '+' || c.is_whitespace())
+                .map(|tag| tag.to_string())
+                .collect();
+            // Written as they are stored
+            let tags = normalization::normalize_all(&tags);
+            if !tags.is_empty(){
+                // The tags, the tags they are aliases of and the tags named
+                // as their aliases
+                let names = vec!["?"; tags.len()].join(",");
+                let mut matches = vec![
+                    format!("t.name IN ({})", names),
+                    format!("t.id IN (SELECT tag_id FROM tag_aliases
+                        WHERE alias IN ({}))", names),
+                    format!("t.name IN (SELECT a.alias FROM tag_aliases a
+                        INNER JOIN tags c ON c.id = a.tag_id
+                        WHERE c.name IN ({}))", names),
+                ];
+                for _ in 0..3{
+                    for tag in tags.iter(){
+                        binds.push(Bind::Text(tag.to_string()));
+                    }
+                }
+                if search.descendants.unwrap_or(false){
+                    for tag in tags.iter(){
+                        for pattern in tag::descendants_patterns(tag){
+                            matches.push("t.name LIKE ? ESCAPE '\\'".to_string());
+                            binds.push(Bind::Text(pattern));
+                        }
+                    }
+                }
+                conditions.push(format!("l.id IN (
+                    SELECT lt.link_id FROM links_tags lt
+                    INNER JOIN tags t ON t.id = lt.tag_id
+                    WHERE {})", matches.join(" OR ")));
+            }
+        }
+        match search.visibility.as_deref(){
+            Some("all") | None => {},
+            Some(value) => {
+                conditions.push("l.private = ?".to_string());
+                binds.push(Bind::Int(("private" == value).into()));
+            },
+        }
+        match search.link_type.as_deref(){
+            Some("note") => conditions.push(format!("l.url LIKE '{}%'", NOTE_PREFIX)),
+            Some("link") => conditions.push(format!("l.url NOT LIKE '{}%'", NOTE_PREFIX)),
+            _ => {},
+        }
+        (conditions, binds)
+    }
+
+    /// Column used to sort the links, `None` to sort by id
+    fn sort_column(sort: &Option<String>)
+            -> Result<Option<(&'static str, Kind)>, Error>{
+        match sort.as_deref(){
+            None | Some("id") => Ok(None),
+            Some("created") => Ok(Some(("l.created", Kind::Datetime))),
+            Some("updated") => Ok(Some(("l.updated", Kind::Datetime))),
+            Some("title") => Ok(Some(("l.title", Kind::Text))),
+            Some("random") => Ok(Some(("random()", Kind::Int))),
+            Some(other) => Err(Error::Protocol(format!("Invalid sort {}", other))),
+        }
+    }
+
+    fn sort_value(link: &LinkWithTags, column: &str) -> Bind{
+        match column {
+            "l.sticky" => Bind::Int(link.sticky.into()),
+            "l.created" => Bind::Datetime(link.created),
+            "l.updated" => Bind::Datetime(link.updated),
+            "l.title" => Bind::Text(link.title.to_string()),
+            _ => Bind::Int(link.id),
+        }
+    }
+
+    /// Links matching the search, sticky first, in pages of `limit` links.
+    /// The next page starts after the cursor `after` or, without it, after
+    /// `offset` links. With `facets` the tags of all of them are counted.
+    pub async fn search(pool: &web::Data<SqlitePool>, search: &LinkSearch)
+            -> Result<LinkPage, Error>{
+        let order = Order::from_param(&search.order);
+        let limit = page::limit(&search.limit);
+        let column = Self::sort_column(&search.sort)?;
+        let random = matches!(column, Some(("random()", _)));
+        let mut columns = vec![("l.sticky", Order::Desc)];
+        let mut kinds = vec![Kind::Int];
+        if let Some((column, kind)) = column{
+            columns.push((column, order));
+            kinds.push(kind);
+        }
+        columns.push(("l.id", order));
+        kinds.push(Kind::Int);
+        let (mut conditions, mut binds) = Self::filter(search);
+        let sql = format!("SELECT count(*) FROM links l WHERE {}",
+            conditions.join(" AND "));
+        let total: i64 = page::bind_all(query(&sql), &binds)
+            .map(|row: SqliteRow| row.get(0))
+            .fetch_one(pool.get_ref())
+            .await?;
+        let facets = if search.facets.unwrap_or(false){
+            let subset = format!("SELECT l.id id FROM links l WHERE {}",
+                conditions.join(" AND "));
+            Some(Tag::cooccurrences(pool, &subset, &binds, None, None).await?)
+        }else{
+            None
         };
-        let mut sql = Vec::new();
-        let mut conditions = Vec::new();
-        sql.push("WITH mtags AS (
+        let mut offset = search.offset.unwrap_or(0).into();
+        if let (Some(after), false) = (&search.after, random){
+            let values = page::read_cursor(after, &kinds)
+                .ok_or_else(|| Error::Protocol("Invalid cursor".to_string()))?;
+            let (condition, after_binds) = page::after_condition(&columns,
+                &values);
+            conditions.push(condition);
+            binds.extend(after_binds);
+            offset = 0;
+        }
+        let sql = format!("WITH mtags AS (
                         SELECT lt.link_id, group_concat(name) tags FROM tags t
                         INNER JOIN links_tags lt ON t.id = lt.tag_id
                         GROUP BY lt.link_id)
                     SELECT l.*,t.tags FROM links l
-                    LEFT JOIN mtags t ON l.id=t.link_id".to_string());
-        conditions.push(match option_searchterm{
-            Some(value) => format!("l.title LIKE '%{}%' or l.description LIKE '%{}%'", value, value),
-            None => "1 = 1".to_string(),
-        });
-        let (sql2, condition2) = match option_searchtags{
-            Some(value) => {
-                let tags = value.split("+")
-                    .map(|x| format!("'{}'", x.trim()))
-                    .collect::<Vec<String>>()
-                    .join(",");
-                ("INNER JOIN links_tags lt ON l.id = lt.link_id
-                  INNER JOIN tags t ON t.id = lt.tag_id".to_string(),
-                format!("t.name IN ({})", tags))
-            },
-            None => ("".to_string(), "1 = 1".to_string()),
-        };
-        sql.push(sql2);
-        conditions.push(condition2);
-        conditions.push(match option_visibility{
-            Some(value) => {
-                if value == "all" {
-                    "1 = 1".to_string()
-                }else{
-                    format!("private = {}", "private" == value)
-                }
-            },
-            None => "1 = 1".to_string(),
-        });
-        sql.push(format!("WHERE {}", conditions.join(" AND ")));
-        sql.push(format!("ORDER BY id"));
-        sql.push(if limit != "all"{
-            format!("LIMIT {} OFFSET {}", limit, offset)
-        }else{
-            "".to_string()
-        });
-        debug!("{}", &sql.join(" "));
-        query(&sql.join(" "))
+                    LEFT JOIN mtags t ON l.id=t.link_id
+                    WHERE {}
+                    ORDER BY {}
+                    LIMIT ? OFFSET ?",
+            conditions.join(" AND "),
+            columns.iter()
+                .map(|(column, order)| format!("{} {}", column, order.sql()))
+                .collect::<Vec<String>>()
+                .join(", "));
+        binds.push(Bind::Int(limit.unwrap_or(-1)));
+        binds.push(Bind::Int(offset));
+        debug!("{}", &sql);
+        let items = page::bind_all(query(&sql), &binds)
             .map(Self::from_row_with_tags)
             .fetch_all(pool.get_ref())
-            .await
+            .await?;
+        let next = match (items.last(), limit){
+            (Some(last), Some(limit)) if !random && items.len() as i64 == limit => {
+                let values: Vec<Value> = columns.iter()
+                    .map(|(column, _)| Self::sort_value(last, column).to_value())
+                    .collect();
+                Some(page::encode_cursor(&values))
+            },
+            _ => None,
+        };
+        Ok(LinkPage{ page: Page{ items, total, next }, facets })
     }
 
     pub async fn update(pool: &web::Data<SqlitePool>, link_id: i64, 
             link_with_tags: &LinkWithTagsNew) -> Result<Link, Error>{
-        let sql = "UPDATE links SET url = $1, title = $2, description = $3,
-                   private = $4, created = $5, updated = &6 WHERE id = $7
-                   RETURNING *";
-        match query(sql)
-            .bind(&link_with_tags.url)
+        let mut tx = pool.begin().await?;
+        Revision::snapshot_with(&mut tx, link_id).await?;
+        // A note keeps its own permalink when no url is given
+        let sql = "UPDATE links SET url = COALESCE(NULLIF($1, ''), url),
+                   url_canonical = COALESCE(NULLIF($2, ''), url_canonical),
+                   title = $3, description = $4, private = $5,
+                   sticky = COALESCE($6, sticky), created = $7, updated = $8
+                   WHERE id = $9 AND deleted_at IS NULL RETURNING *";
+        let link = query(sql)
+            .bind(link_with_tags.url.trim())
+            .bind(canonical_url::canonicalize(&link_with_tags.url))
+            .bind(&link_with_tags.title)
+            .bind(&link_with_tags.description)
+            .bind(link_with_tags.private)
+            .bind(link_with_tags.sticky)
+            .bind(link_with_tags.created)
+            .bind(link_with_tags.updated)
+            .bind(link_id)
+            .map(Self::from_row)
+            .fetch_one(&mut tx)
+            .await?;
+        tx.commit().await?;
+        Ok(link)
+    }
+
+    /// Change only the fields given, the tags included, keeping the others.
+    /// Used by sync, where a client sends the fields it changed.
+    pub async fn patch(pool: &web::Data<SqlitePool>, link_id: i64,
+            link_with_tags: &LinkWithTagsNew) -> Result<Link, Error>{
+        let mut tx = pool.begin().await?;
+        Revision::snapshot_with(&mut tx, link_id).await?;
+        // A note keeps its own permalink when no url is given
+        let sql = "UPDATE links SET url = COALESCE(NULLIF($1, ''), url),
+                   url_canonical = COALESCE(NULLIF($2, ''), url_canonical),
+                   title = COALESCE($3, title),
+                   description = COALESCE($4, description),
+                   private = COALESCE($5, private),
+                   sticky = COALESCE($6, sticky),
+                   created = COALESCE($7, created),
+                   updated = COALESCE($8, $9) WHERE id = $10
+                   AND deleted_at IS NULL RETURNING *";
+        let link = query(sql)
+            .bind(link_with_tags.url.trim())
+            .bind(canonical_url::canonicalize(&link_with_tags.url))
             .bind(&link_with_tags.title)
             .bind(&link_with_tags.description)
-            .bind(&link_with_tags.private)
-            .bind(&link_with_tags.created)
-            .bind(&link_with_tags.updated)
+            .bind(link_with_tags.private)
+            .bind(link_with_tags.sticky)
+            .bind(link_with_tags.created)
+            .bind(link_with_tags.updated)
+            .bind(Utc::now())
+            .bind(link_id)
+            .map(Self::from_row)
+            .fetch_one(&mut tx)
+            .await?;
+        if let Some(tags_names) = &link_with_tags.tags{
+            Self::set_tags(&mut tx, link_id, tags_names).await?;
+        }
+        tx.commit().await?;
+        Ok(link)
+    }
+
+    /// Replace the tags of the link, within the transaction of the change
+    pub async fn set_tags(tx: &mut Transaction<'_, Sqlite>, link_id: i64,
+            tags_names: &[String]) -> Result<(), Error>{
+        let sql = "DELETE FROM links_tags WHERE link_id = $1";
+        query(sql)
+            .bind(link_id)
+            .execute(&mut *tx)
+            .await?;
+        for tag_name in normalization::normalize_all(tags_names).iter(){
+            let tag_name = match Alias::resolve(&mut *tx, tag_name).await?{
+                Some(tag) => tag.name,
+                None => tag_name.to_string(),
+            };
+            let sql = "INSERT INTO tags (name) VALUES ($1)
+                       ON CONFLICT(name) DO NOTHING";
+            query(sql).bind(&tag_name).execute(&mut *tx).await?;
+            let sql = "INSERT OR IGNORE INTO links_tags (link_id, tag_id)
+                       SELECT $1, id FROM tags WHERE name = $2";
+            query(sql).bind(link_id).bind(&tag_name).execute(&mut *tx).await?;
+        }
+        Tag::collect_garbage(&mut *tx).await?;
+        Ok(())
+    }
+
+    /// Sticky links are listed before the others
+    pub async fn pin(pool: &web::Data<SqlitePool>, link_id: i64, sticky: bool)
+            -> Result<LinkWithTags, Error>{
+        Revision::snapshot(pool, link_id).await?;
+        let sql = "UPDATE links SET sticky = $1, updated = $2 WHERE id = $3
+                   AND deleted_at IS NULL RETURNING *";
+        query(sql)
+            .bind(sticky)
+            .bind(Utc::now())
             .bind(link_id)
             .map(Self::from_row)
             .fetch_one(pool.get_ref())
-            .await{
-                Ok(l) => {
-                    Ok(l)
-                },
-                Err(e) => Err(e),
-            }
+            .await?;
+        Self::read(pool, link_id).await
     }
 
+    /// Move the link to the trash
     pub async fn delete(pool: &web::Data<SqlitePool>, link_id: i64) 
             -> Result<bool, Error>{
-        let sql = "DELETE FROM links WHERE id = $1";
+        let sql = "UPDATE links SET deleted_at = $1 WHERE id = $2
+                   AND deleted_at IS NULL";
         debug!("Delete: {}", sql);
         match query(sql)
+            .bind(Utc::now())
             .bind(link_id)
             .execute(pool.get_ref())
             .await{
-                Ok(_) => {
-                    Ok(true)
+                Ok(result) => {
+                    Ok(result.rows_affected() > 0)
                 },
                 Err(e) => Err(e),
             }
     }
 
+    pub async fn read_trash(pool: &web::Data<SqlitePool>,
+            option_offset: &Option<i32>, option_limit: &Option<String>)
+            -> Result<Vec<LinkWithTags>, Error>{
+        let offset = option_offset.unwrap_or(0);
+        let limit = match option_limit {
+            Some(v) if v == "all" => -1,
+            Some(v) => v.parse::<i64>().unwrap_or(20),
+            None => 20,
+        };
+        let sql = " WITH mtags AS (
+                        SELECT lt.link_id, group_concat(name) tags FROM tags t
+                        INNER JOIN links_tags lt ON t.id = lt.tag_id
+                        GROUP BY lt.link_id)
+                    SELECT l.*,t.tags FROM links l
+                    LEFT JOIN mtags t ON l.id=t.link_id
+                    WHERE l.deleted_at IS NOT NULL
+                    ORDER BY l.deleted_at DESC
+                    LIMIT $1 OFFSET $2";
+        query(sql)
+            .bind(limit)
+            .bind(offset)
+            .map(Self::from_row_with_tags)
+            .fetch_all(pool.get_ref())
+            .await
+    }
+
+    /// Take the link out of the trash
+    pub async fn restore(pool: &web::Data<SqlitePool>, link_id: i64)
+            -> Result<LinkWithTags, Error>{
+        let sql = "UPDATE links SET deleted_at = NULL WHERE id = $1
+                   AND deleted_at IS NOT NULL RETURNING *";
+        query(sql)
+            .bind(link_id)
+            .map(Self::from_row)
+            .fetch_one(pool.get_ref())
+            .await?;
+        Self::read(pool, link_id).await
+    }
+
+    /// Remove for good the links in the trash, with their tags relations,
+    /// revisions and the tags no longer used
+    async fn purge(pool: &web::Data<SqlitePool>, links_ids: &[i64])
+            -> Result<u64, Error>{
+        let mut tx = pool.begin().await?;
+        let mut purged = 0;
+        for link_id in links_ids{
+            // Only the links in the trash, the others keep their revisions
+            let sql = "SELECT id FROM links WHERE id = $1
+                       AND deleted_at IS NOT NULL";
+            let trashed = query(sql)
+                .bind(link_id)
+                .fetch_optional(&mut tx)
+                .await?;
+            if trashed.is_none(){
+                continue;
+            }
+            let sql = "DELETE FROM link_revisions WHERE link_id = $1";
+            query(sql)
+                .bind(link_id)
+                .execute(&mut tx)
+                .await?;
+            // Its rows in links_tags are deleted on cascade
+            let sql = "DELETE FROM links WHERE id = $1
+                       AND deleted_at IS NOT NULL";
+            purged += query(sql)
+                .bind(link_id)
+                .execute(&mut tx)
+                .await?
+                .rows_affected();
+        }
+        Tag::collect_garbage(&mut tx).await?;
+        tx.commit().await?;
+        Ok(purged)
+    }
+
+    pub async fn purge_one(pool: &web::Data<SqlitePool>, link_id: i64)
+            -> Result<u64, Error>{
+        Self::purge(pool, &[link_id]).await
+    }
+
+    /// Purge the links that have been in the trash for more than `days`
+    pub async fn purge_trash(pool: &web::Data<SqlitePool>, days: i64)
+            -> Result<u64, Error>{
+        let sql = "SELECT id FROM links WHERE deleted_at < $1";
+        let links_ids: Vec<i64> = query(sql)
+            .bind(Utc::now() - Duration::days(days))
+            .map(|row: SqliteRow| row.get("id"))
+            .fetch_all(pool.get_ref())
+            .await?;
+        debug!("Purge {} links from the trash", links_ids.len());
+        Self::purge(pool, &links_ids).await
+    }
+
     pub async fn drop(pool: &web::Data<SqlitePool>) 
             -> Result<SqliteQueryResult, Error>{
         let sql = "DELETE FROM links;";
@@ -327,40 +867,8 @@ impl Link{
 
 #[cfg(test)]
 mod tests {
-    use std::{env, path::Path};
-    use sqlx::{Sqlite, Pool, sqlite::SqlitePoolOptions, migrate::{Migrator,
-        MigrateDatabase}};
-    use actix_web::web::Data;
-    use super::{Tag, Link, LinkWithTagsNew};
-    use dotenv::dotenv;
-
-    async fn setup() -> Data<Pool<Sqlite>>{
-        dotenv().ok();
-        let db_url = env::var("DATABASE_URL").expect("DATABASE_URL not set");
-        if !Sqlite::database_exists(&db_url).await.unwrap(){
-            Sqlite::create_database(&db_url).await.unwrap()
-        }
-        let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
-        let migrations = Path::new(&crate_dir).join("migrations");
-        println!("{}", migrations.to_str().unwrap());
-
-    let pool = SqlitePoolOptions::new()
-        .max_connections(1)
-        .connect(&db_url)
-        .await
-        .expect("pool failed");
-
-    Migrator::new(migrations)
-        .await.unwrap()
-        .run(&pool)
-        .await.unwrap();
-
-        Data::new(pool)
-    }
-
-    async fn teardown(pool: &Data<Pool<Sqlite>>){
-        let _result = Link::drop(pool).await;
-    }
+    use crate::models::test_utils::{setup, new_link};
+    use super::{Tag, Link, LinkWithTagsNew, LinkSearch, Revision, NOTE_PREFIX};
 
     #[tokio::test]
     async fn create(){
@@ -371,6 +879,7 @@ mod tests {
             description: None,
             tags: None,
             private: None,
+            sticky: None,
             created: None,
             updated: None,
         };
@@ -382,7 +891,6 @@ mod tests {
             },
             Err(_) => assert!(false),
         }
-        teardown(&pool).await;
     }
 
     #[tokio::test]
@@ -395,7 +903,6 @@ mod tests {
             },
             Err(_) => assert!(false),
         }
-        teardown(&pool).await;
     }
 
     #[tokio::test]
@@ -406,7 +913,6 @@ mod tests {
         let _ = Link::create(&pool, "https://github.com").await;
         let links = Link::read_all(&pool).await.unwrap();
         assert_eq!(links.len(), 3);
-        teardown(&pool).await;
     }
 
     #[tokio::test]
@@ -416,6 +922,155 @@ mod tests {
         let _ = Link::delete(&pool, link.id).await;
         let tags = Link::read_all(&pool).await.unwrap();
         assert_eq!(tags.len(), 0);
-        teardown(&pool).await;
+    }
+
+    #[tokio::test]
+    async fn read_from_canonical(){
+        let pool = setup().await;
+        let new_link = LinkWithTagsNew {
+            url: "https://example.com/a/?utm_source=feed#top".to_string(),
+            title: Some("Example".to_string()),
+            description: Some("".to_string()),
+            tags: Some(Vec::new()),
+            private: None,
+            sticky: None,
+            created: None,
+            updated: None,
+        };
+        let link = Link::create_from_post(&pool, &new_link).await.unwrap();
+        let test = Link::read_from_canonical(&pool, "https://EXAMPLE.com/a")
+            .await.unwrap();
+        assert_eq!(test.id, link.id);
+    }
+
+    #[tokio::test]
+    async fn create_note(){
+        let pool = setup().await;
+        let new_link = LinkWithTagsNew {
+            url: "".to_string(),
+            title: Some("A note".to_string()),
+            description: Some("Without url".to_string()),
+            tags: Some(Vec::new()),
+            private: None,
+            sticky: None,
+            created: None,
+            updated: None,
+        };
+        let link = Link::create_from_post(&pool, &new_link).await.unwrap();
+        assert_eq!(link.url, format!("{}{}", NOTE_PREFIX, link.shorturl));
+    }
+
+    #[tokio::test]
+    async fn trash(){
+        let pool = setup().await;
+        let new_link = LinkWithTagsNew {
+            url: "https://example.com/trash".to_string(),
+            title: Some("Trash".to_string()),
+            description: Some("".to_string()),
+            tags: Some(vec!["trash".to_string()]),
+            private: None,
+            sticky: None,
+            created: None,
+            updated: None,
+        };
+        let link = Link::create_from_post(&pool, &new_link).await.unwrap();
+        assert!(Link::delete(&pool, link.id).await.unwrap());
+        assert!(Link::read(&pool, link.id).await.is_err());
+        assert_eq!(Link::read_trash(&pool, &None, &None).await.unwrap().len(), 1);
+        Link::restore(&pool, link.id).await.unwrap();
+        assert!(Link::delete(&pool, link.id).await.unwrap());
+        assert_eq!(Link::purge_one(&pool, link.id).await.unwrap(), 1);
+        assert!(Tag::read_from_name(&pool, "trash").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn purge_live(){
+        let pool = setup().await;
+        let link = Link::create_from_post(&pool,
+            &new_link("https://example.com/live", &[])).await.unwrap();
+        Link::pin(&pool, link.id, true).await.unwrap();
+        assert_eq!(Link::purge_one(&pool, link.id).await.unwrap(), 0);
+        let revisions = Revision::read_for_link(&pool, link.id).await.unwrap();
+        assert_eq!(revisions.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn search_with_cursor(){
+        let pool = setup().await;
+        for title in ["c", "a", "b"]{
+            let new_link = LinkWithTagsNew {
+                url: format!("https://example.com/{}", title),
+                title: Some(title.to_string()),
+                description: Some("".to_string()),
+                tags: Some(Vec::new()),
+                private: None,
+                sticky: None,
+                created: None,
+                updated: None,
+            };
+            Link::create_from_post(&pool, &new_link).await.unwrap();
+        }
+        let mut search = LinkSearch{
+            limit: Some("2".to_string()),
+            sort: Some("title".to_string()),
+            order: Some("desc".to_string()),
+            ..Default::default()
+        };
+        let page = Link::search(&pool, &search).await.unwrap().page;
+        assert_eq!(page.total, 3);
+        assert_eq!(page.items.iter().map(|l| l.title.as_str()).collect::<Vec<&str>>(),
+            vec!["c", "b"]);
+        search.after = page.next;
+        let page = Link::search(&pool, &search).await.unwrap().page;
+        assert_eq!(page.items.len(), 1);
+        assert_eq!(page.items[0].title, "a");
+        assert!(page.next.is_none());
+    }
+
+    #[tokio::test]
+    async fn search_facets(){
+        let pool = setup().await;
+        Link::create_from_post(&pool, &new_link("https://a.es", &["rust", "web"]))
+            .await.unwrap();
+        Link::create_from_post(&pool, &new_link("https://b.es", &["rust"]))
+            .await.unwrap();
+        Link::create_from_post(&pool, &new_link("https://c.es", &["python"]))
+            .await.unwrap();
+        let search = LinkSearch{
+            limit: Some("1".to_string()),
+            searchtags: Some("rust".to_string()),
+            facets: Some(true),
+            ..Default::default()
+        };
+        let found = Link::search(&pool, &search).await.unwrap();
+        assert_eq!(found.page.items.len(), 1);
+        // Counted over both links, not only the one in the page
+        let facets = found.facets.unwrap();
+        assert_eq!(facets.iter().map(|f| (f.name.as_str(), f.occurrences))
+            .collect::<Vec<(&str, i64)>>(), vec![("rust", 2), ("web", 1)]);
+        let search = LinkSearch{ facets: None, ..search };
+        assert!(Link::search(&pool, &search).await.unwrap().facets.is_none());
+    }
+
+    #[tokio::test]
+    async fn canonicalize_duplicates(){
+        let pool = setup().await;
+        // As stored before the canonical urls
+        let clear = || sqlx::query("UPDATE links SET url_canonical = ''")
+            .execute(pool.get_ref());
+        let first = Link::create_from_post(&pool, &new_link("https://x.com/a", &["rust"]))
+            .await.unwrap();
+        clear().await.unwrap();
+        let second = Link::create_from_post(&pool,
+            &new_link("https://x.com/a?utm_source=feed", &["web"])).await.unwrap();
+        clear().await.unwrap();
+        Link::canonicalize_all(&pool).await;
+        let kept = Link::read(&pool, first.id).await.unwrap();
+        assert_eq!(kept.tags, vec!["rust", "web"]);
+        let merged = Link::read_from_canonical(&pool, &second.url).await.unwrap();
+        assert_eq!(merged.id, first.id);
+        // The duplicate is in the trash
+        assert!(Link::read(&pool, second.id).await.is_err());
+        assert_eq!(Link::read_all(&pool).await.unwrap().len(), 1);
     }
 }
@@ -83,85 +83,77 @@ impl LinkTag {
 
 #[cfg(test)]
 mod tests {
-    use std::{env, path::Path};
-    use sqlx::{Sqlite, Pool, sqlite::SqlitePoolOptions, migrate::{Migrator,
-        MigrateDatabase}, SqlitePool};
+    use sqlx::SqlitePool;
     use actix_web::web::Data;
     use super::LinkTag;
-    use dotenv::dotenv;
-
-    async fn setup() -> Data<SqlitePool>{
-        dotenv().ok();
-        let db_url = env::var("DATABASE_URL").expect("DATABASE_URL not set");
-        if !Sqlite::database_exists(&db_url).await.unwrap(){
-            Sqlite::create_database(&db_url).await.unwrap()
+    use crate::models::{link::Link, tag::Tag, test_utils::{setup, new_link}};
+
+    /// A link and three tags to join, as links_tags references both
+    async fn rows(pool: &Data<SqlitePool>) -> (i64, Vec<i64>){
+        let link = Link::create_from_post(pool,
+            &new_link("https://example.com/link_tag", &[])).await.unwrap();
+        let mut tags_ids = Vec::new();
+        for name in ["uno", "dos", "tres"]{
+            tags_ids.push(Tag::create(pool, name).await.unwrap().id);
         }
-        let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
-        let migrations = Path::new(&crate_dir).join("migrations");
-        println!("{}", migrations.to_str().unwrap());
-
-    let pool = SqlitePoolOptions::new()
-        .max_connections(1)
-        .connect(&db_url)
-        .await
-        .expect("pool failed");
-
-    Migrator::new(migrations)
-        .await.unwrap()
-        .run(&pool)
-        .await.unwrap();
-
-        Data::new(pool)
-    }
-
-    async fn teardown(pool: &Data<SqlitePool>){
-        let _result = LinkTag::drop(pool).await;
+        (link.id, tags_ids)
     }
 
     #[tokio::test]
     async fn create(){
         let pool = setup().await;
-        match LinkTag::create(&pool, 1, 1).await {
+        let (link_id, tags_ids) = rows(&pool).await;
+        match LinkTag::create(&pool, link_id, tags_ids[0]).await {
             Ok(linktag) => {
-                assert_eq!(linktag.link_id, 1);
-                assert_eq!(linktag.tag_id, 1);
+                assert_eq!(linktag.link_id, link_id);
+                assert_eq!(linktag.tag_id, tags_ids[0]);
             },
             Err(_) => assert!(false),
         }
-        teardown(&pool).await;
     }
 
     #[tokio::test]
     async fn read_one(){
         let pool = setup().await;
-        match LinkTag::create(&pool, 1, 1).await {
+        let (link_id, tags_ids) = rows(&pool).await;
+        match LinkTag::create(&pool, link_id, tags_ids[0]).await {
             Ok(linktag) => {
                 let test = LinkTag::read(&pool, linktag.id).await.unwrap();
                 assert_eq!(test, linktag);
             },
             Err(_) => assert!(false),
         }
-        teardown(&pool).await;
     }
 
     #[tokio::test]
     async fn read_all(){
         let pool = setup().await;
-        let _ = LinkTag::create(&pool, 1, 1).await;
-        let _ = LinkTag::create(&pool, 1, 2).await;
-        let _ = LinkTag::create(&pool, 1, 3).await;
+        let (link_id, tags_ids) = rows(&pool).await;
+        for tag_id in tags_ids{
+            let _ = LinkTag::create(&pool, link_id, tag_id).await;
+        }
         let links_tags = LinkTag::read_all(&pool).await.unwrap();
         assert_eq!(links_tags.len(), 3);
-        teardown(&pool).await;
     }
 
     #[tokio::test]
     async fn delete(){
         let pool = setup().await;
-        let link_tag = LinkTag::create(&pool, 1, 1).await.unwrap();
+        let (link_id, tags_ids) = rows(&pool).await;
+        let link_tag = LinkTag::create(&pool, link_id, tags_ids[0]).await.unwrap();
         let _ = LinkTag::delete(&pool, link_tag.id).await;
         let links_tags = LinkTag::read_all(&pool).await.unwrap();
         assert_eq!(links_tags.len(), 0);
-        teardown(&pool).await;
+    }
+
+    #[tokio::test]
+    async fn cascade(){
+        let pool = setup().await;
+        let (link_id, tags_ids) = rows(&pool).await;
+        let _ = LinkTag::create(&pool, link_id, tags_ids[0]).await;
+        assert!(LinkTag::create(&pool, link_id, 0).await.is_err());
+        let _ = Link::drop(&pool).await;
+        let links_tags = LinkTag::read_all(&pool).await.unwrap();
+        assert_eq!(links_tags.len(), 0);
     }
 }
@@ -6,3 +6,15 @@ pub mod short_url;
 pub mod claim;
 pub mod general;
 pub mod history;
+pub mod suggestion;
+pub mod canonical_url;
+pub mod revision;
+pub mod bulk;
+pub mod page;
+pub mod alias;
+pub mod normalization;
+pub mod saved_search;
+pub mod webhook;
+pub mod sync;
+#[cfg(test)]
+pub mod test_utils;
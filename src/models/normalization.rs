@@ -0,0 +1,118 @@
+/// # Tag normalization
+/// Rules applied to the name of every tag before storing it, so the same
+/// tag is not written in several ways.
+///
+/// Each rule is configured with an env var:
+/// * `TAG_LOWERCASE`: `true` to lowercase the names (default `false`)
+/// * `TAG_WHITESPACE`: text that replaces each run of whitespace (default
+///   `-`), as whitespace separates the tags of a search
+/// * `TAG_FOLD_ACCENTS`: `true` to remove accents and other diacritics
+///   (default `false`)
+/// * `TAG_MAX_LENGTH`: maximum number of characters, `0` for no limit
+///   (default `0`)
+/// * `TAG_FORBIDDEN`: characters removed from the names (default `+,`, the
+///   separators of tags in searches and revisions)
+///
+/// ## Example
+/// * `Ciencia  Ficción` => `ciencia-ficcion` (lowercase and accent folding)
+use std::env;
+use unicode_normalization::{UnicodeNormalization, char::is_combining_mark};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rules {
+    pub lowercase: bool,
+    pub whitespace: String,
+    pub fold_accents: bool,
+    pub max_length: usize,
+    pub forbidden: String,
+}
+
+impl Default for Rules{
+    fn default() -> Self{
+        Self{
+            lowercase: false,
+            whitespace: "-".to_string(),
+            fold_accents: false,
+            max_length: 0,
+            forbidden: "+,".to_string(),
+        }
+    }
+}
+
+impl Rules{
+    /// Rules configured with the `TAG_*` env vars
+    pub fn from_env() -> Self{
+        let default = Self::default();
+        Self{
+            lowercase: flag("TAG_LOWERCASE", default.lowercase),
+            whitespace: env::var("TAG_WHITESPACE").unwrap_or(default.whitespace),
+            fold_accents: flag("TAG_FOLD_ACCENTS", default.fold_accents),
+            max_length: env::var("TAG_MAX_LENGTH")
+                .ok()
+                .and_then(|v| v.parse::<usize>().ok())
+                .unwrap_or(default.max_length),
+            forbidden: env::var("TAG_FORBIDDEN").unwrap_or(default.forbidden),
+        }
+    }
+
+    pub fn apply(&self, name: &str) -> String{
+        let mut name: String = name.chars()
+            .filter(|c| !self.forbidden.contains(*c))
+            .collect::<String>()
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(&self.whitespace);
+        if self.fold_accents{
+            name = name.nfd()
+                .filter(|c| !is_combining_mark(*c))
+                .nfc()
+                .collect();
+        }
+        if self.lowercase{
+            name = name.to_lowercase();
+        }
+        if self.max_length > 0{
+            name = name.chars().take(self.max_length).collect();
+        }
+        name
+    }
+}
+
+/// Name of the tag after the rules configured in the env
+pub fn normalize(name: &str) -> String{
+    Rules::from_env().apply(name)
+}
+
+/// Normalized names without the empty and the repeated ones
+pub fn normalize_all(names: &[String]) -> Vec<String>{
+    let rules = Rules::from_env();
+    let mut normalized: Vec<String> = Vec::new();
+    for name in names.iter().map(|name| rules.apply(name)){
+        if !name.is_empty() && !normalized.contains(&name){
+            normalized.push(name);
+        }
+    }
+    normalized
+}
+
+fn flag(key: &str, default: bool) -> bool{
+    match env::var(key){
+        Ok(v) => matches!(v.to_lowercase().as_str(), "true" | "1" | "yes"),
+        Err(_) => default,
+    }
+}
+
+#[test]
+fn test_normalize(){
+    let rules = Rules::default();
+    assert_eq!(rules.apply("  Ciencia  Ficción "), "Ciencia-Ficción");
+    assert_eq!(rules.apply("c++,rust"), "crust");
+    let rules = Rules{
+        lowercase: true,
+        fold_accents: true,
+        max_length: 10,
+        ..Rules::default()
+    };
+    assert_eq!(rules.apply("Ciencia  Ficción"), "ciencia-fi");
+    assert_eq!(rules.apply("lang:Rust"), "lang:rust");
+}
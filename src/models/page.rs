@@ -0,0 +1,165 @@
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use chrono::{DateTime, Utc};
+use sqlx::{sqlite::{Sqlite, SqliteArguments}, query::Query};
+
+pub const DEFAULT_LIMIT: i64 = 20;
+
+/// A page of results with the total of items matching the search and the
+/// cursor to get the next page, if there are more
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: i64,
+    pub next: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    Asc,
+    Desc,
+}
+
+impl Order{
+    pub fn from_param(order: &Option<String>) -> Order{
+        match order.as_deref(){
+            Some("desc") => Order::Desc,
+            _ => Order::Asc,
+        }
+    }
+
+    pub fn sql(&self) -> &'static str{
+        match self {
+            Order::Asc => "ASC",
+            Order::Desc => "DESC",
+        }
+    }
+
+    /// Operator to get the rows after the cursor
+    pub fn after(&self) -> &'static str{
+        match self {
+            Order::Asc => ">",
+            Order::Desc => "<",
+        }
+    }
+}
+
+/// Value bound to a query built at runtime
+#[derive(Debug, Clone)]
+pub enum Bind {
+    Int(i64),
+    Text(String),
+    Datetime(DateTime<Utc>),
+}
+
+impl Bind{
+    /// Value of the sort column as stored in a cursor
+    pub fn to_value(&self) -> Value{
+        match self {
+            Bind::Int(v) => Value::from(*v),
+            Bind::Text(v) => Value::from(v.to_string()),
+            Bind::Datetime(v) => Value::from(v.to_rfc3339()),
+        }
+    }
+
+}
+
+/// Type of a sort column, to read back its value from a cursor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Int,
+    Text,
+    Datetime,
+}
+
+impl Kind{
+    pub fn read(&self, value: &Value) -> Option<Bind>{
+        match self {
+            Kind::Int => value.as_i64().map(Bind::Int),
+            Kind::Text => value.as_str().map(|v| Bind::Text(v.to_string())),
+            Kind::Datetime => value.as_str()
+                .and_then(|v| DateTime::parse_from_rfc3339(v).ok())
+                .map(|v| Bind::Datetime(v.with_timezone(&Utc))),
+        }
+    }
+}
+
+/// Read the values of a cursor for the columns of the given kinds
+pub fn read_cursor(cursor: &str, kinds: &[Kind]) -> Option<Vec<Bind>>{
+    let values = decode_cursor(cursor)?;
+    if values.len() != kinds.len(){
+        return None;
+    }
+    kinds.iter()
+        .zip(values.iter())
+        .map(|(kind, value)| kind.read(value))
+        .collect()
+}
+
+pub fn bind_all<'q>(mut query: Query<'q, Sqlite, SqliteArguments<'q>>,
+        binds: &[Bind]) -> Query<'q, Sqlite, SqliteArguments<'q>>{
+    for bind in binds{
+        query = match bind {
+            Bind::Int(v) => query.bind(*v),
+            Bind::Text(v) => query.bind(v.to_string()),
+            Bind::Datetime(v) => query.bind(*v),
+        };
+    }
+    query
+}
+
+/// Maximum number of rows, `None` for all of them
+pub fn limit(option_limit: &Option<String>) -> Option<i64>{
+    match option_limit {
+        Some(v) if v == "all" => None,
+        Some(v) => Some(v.parse::<i64>().unwrap_or(DEFAULT_LIMIT)),
+        None => Some(DEFAULT_LIMIT),
+    }
+}
+
+/// Opaque cursor made of the values of the sort columns of the last item
+pub fn encode_cursor(values: &[Value]) -> String{
+    let json = Value::Array(values.to_vec()).to_string();
+    base64::encode_config(json, base64::URL_SAFE_NO_PAD)
+}
+
+pub fn decode_cursor(cursor: &str) -> Option<Vec<Value>>{
+    let json = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD).ok()?;
+    match serde_json::from_slice::<Value>(&json).ok()?{
+        Value::Array(values) => Some(values),
+        _ => None,
+    }
+}
+
+/// Condition to get the rows after the ones with `values` in the sort
+/// `columns`, each of them with its own order. Uses positional parameters.
+pub fn after_condition(columns: &[(&str, Order)], values: &[Bind])
+        -> (String, Vec<Bind>){
+    let mut condition = String::new();
+    let mut binds = Vec::new();
+    for ((column, order), value) in columns.iter().zip(values.iter()).rev(){
+        if condition.is_empty(){
+            condition = format!("{} {} ?", column, order.after());
+            binds.push(value.clone());
+        }else{
+            condition = format!("({} {} ? OR ({} = ? AND {}))", column,
+                order.after(), column, condition);
+            binds.insert(0, value.clone());
+            binds.insert(0, value.clone());
+        }
+    }
+    (condition, binds)
+}
+
+#[test]
+fn test_cursor(){
+    let values = vec![Value::from(1), Value::from("title"), Value::from(3)];
+    let cursor = encode_cursor(&values);
+    assert_eq!(decode_cursor(&cursor).unwrap(), values);
+    assert!(decode_cursor("not a cursor").is_none());
+    let (condition, binds) = after_condition(
+        &[("a", Order::Desc), ("b", Order::Asc)],
+        &[Bind::Int(1), Bind::Int(2)]);
+    assert_eq!(condition, "(a < ? OR (a = ? AND b > ?))");
+    assert_eq!(binds.len(), 3);
+}
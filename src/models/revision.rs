@@ -0,0 +1,240 @@
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+use chrono::{DateTime, Utc};
+use actix_web::web;
+use sqlx::{sqlite::{SqlitePool, SqliteRow, Sqlite},
+    Executor, Error, query, Row};
+use log::debug;
+
+use super::{link::{Link, LinkWithTags}, canonical_url};
+
+/// State of a link just before it was changed
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Revision {
+    pub id: i64,
+    pub link_id: i64,
+    pub url: String,
+    pub title: String,
+    pub description: String,
+    pub tags: Vec<String>,
+    pub private: bool,
+    pub sticky: bool,
+    pub updated: DateTime<Utc>,
+    pub datetime: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Change {
+    pub field: String,
+    pub old: Value,
+    pub new: Value,
+}
+
+/// A revision with the changes made over it by the next one
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RevisionWithChanges {
+    #[serde(flatten)]
+    pub revision: Revision,
+    pub changes: Vec<Change>,
+}
+
+impl Revision{
+    fn from_row(row: SqliteRow) -> Revision{
+        let tags = row.get::<String, &str>("tags");
+        Revision {
+            id: row.get("id"),
+            link_id: row.get("link_id"),
+            url: row.get("url"),
+            title: row.get("title"),
+            description: row.get("description"),
+            tags: tags.split(',')
+                .filter(|tag| !tag.is_empty())
+                .map(|tag| tag.to_string())
+                .collect(),
+            private: row.get("private"),
+            sticky: row.get("sticky"),
+            updated: row.get("updated"),
+            datetime: row.get("dt"),
+        }
+    }
+
+    /// Store the current state of the link before changing it
+    pub async fn snapshot(pool: &web::Data<SqlitePool>, link_id: i64)
+            -> Result<Revision, Error>{
+        Self::snapshot_with(pool.get_ref(), link_id).await
+    }
+
+    /// Same as `snapshot` but within a transaction
+    pub async fn snapshot_with<'c, E>(executor: E, link_id: i64)
+            -> Result<Revision, Error>
+            where E: Executor<'c, Database = Sqlite>{
+        let sql = "INSERT INTO link_revisions (link_id, url, title,
+                   description, tags, private, sticky, updated, dt)
+                   SELECT l.id, l.url, l.title, l.description,
+                        IFNULL((SELECT group_concat(t.name) FROM tags t
+                                INNER JOIN links_tags lt ON t.id = lt.tag_id
+                                WHERE lt.link_id = l.id), ''),
+                        l.private, l.sticky, l.updated, $1
+                   FROM links l WHERE l.id = $2 AND l.deleted_at IS NULL
+                   RETURNING *;";
+        query(sql)
+            .bind(Utc::now())
+            .bind(link_id)
+            .map(Self::from_row)
+            .fetch_one(executor)
+            .await
+    }
+
+    pub async fn read(pool: &web::Data<SqlitePool>, link_id: i64, id: i64)
+            -> Result<Revision, Error>{
+        let sql = "SELECT * FROM link_revisions WHERE link_id = $1 AND id = $2";
+        query(sql)
+            .bind(link_id)
+            .bind(id)
+            .map(Self::from_row)
+            .fetch_one(pool.get_ref())
+            .await
+    }
+
+    /// Revisions of the link, newest first, with the changes made over each
+    /// one by the following revision or by the current state of the link
+    pub async fn read_for_link(pool: &web::Data<SqlitePool>, link_id: i64)
+            -> Result<Vec<RevisionWithChanges>, Error>{
+        let link = Link::read(pool, link_id).await?;
+        let sql = "SELECT * FROM link_revisions WHERE link_id = $1
+                   ORDER BY id DESC";
+        let revisions = query(sql)
+            .bind(link_id)
+            .map(Self::from_row)
+            .fetch_all(pool.get_ref())
+            .await?;
+        let mut next = Self::fields_from_link(&link);
+        let mut result = Vec::new();
+        for revision in revisions{
+            let fields = revision.fields();
+            let changes = diff(&fields, &next);
+            next = fields;
+            result.push(RevisionWithChanges{ revision, changes });
+        }
+        Ok(result)
+    }
+
+    /// Set the link back to the state stored in the revision. The current
+    /// state is kept as a new revision, so a restore can be undone.
+    pub async fn restore(pool: &web::Data<SqlitePool>, link_id: i64, id: i64)
+            -> Result<LinkWithTags, Error>{
+        let revision = Self::read(pool, link_id, id).await?;
+        let mut tx = pool.begin().await?;
+        // A link in the trash has no snapshot, it leaves the trash first
+        Self::snapshot_with(&mut tx, link_id).await?;
+        debug!("Restore link {} to revision {}", link_id, id);
+        let sql = "UPDATE links SET url = $1, url_canonical = $2, title = $3,
+                   description = $4, private = $5, sticky = $6, updated = $7
+                   WHERE id = $8 AND deleted_at IS NULL";
+        let result = query(sql)
+            .bind(&revision.url)
+            .bind(canonical_url::canonicalize(&revision.url))
+            .bind(&revision.title)
+            .bind(&revision.description)
+            .bind(revision.private)
+            .bind(revision.sticky)
+            .bind(Utc::now())
+            .bind(link_id)
+            .execute(&mut tx)
+            .await?;
+        if result.rows_affected() == 0{
+            return Err(Error::RowNotFound);
+        }
+        Link::set_tags(&mut tx, link_id, &revision.tags).await?;
+        tx.commit().await?;
+        Link::read(pool, link_id).await
+    }
+
+    fn fields(&self) -> Vec<(&'static str, Value)>{
+        let mut tags = self.tags.clone();
+        tags.sort();
+        vec![
+            ("url", json!(self.url)),
+            ("title", json!(self.title)),
+            ("description", json!(self.description)),
+            ("tags", json!(tags)),
+            ("private", json!(self.private)),
+            ("sticky", json!(self.sticky)),
+        ]
+    }
+
+    fn fields_from_link(link: &LinkWithTags) -> Vec<(&'static str, Value)>{
+        let mut tags = link.tags.clone();
+        tags.sort();
+        vec![
+            ("url", json!(link.url)),
+            ("title", json!(link.title)),
+            ("description", json!(link.description)),
+            ("tags", json!(tags)),
+            ("private", json!(link.private)),
+            ("sticky", json!(link.sticky)),
+        ]
+    }
+}
+
+fn diff(old: &[(&'static str, Value)], new: &[(&'static str, Value)]) -> Vec<Change>{
+    old.iter()
+        .zip(new.iter())
+        .filter(|((_, old), (_, new))| old != new)
+        .map(|((field, old), (_, new))| Change{
+            field: field.to_string(),
+            old: old.clone(),
+            new: new.clone(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use super::Revision;
+    use crate::models::{link::{Link, LinkWithTagsNew},
+        test_utils::{setup, new_link}};
+
+    /// Updates replace every field, the dates included
+    fn titled(title: &str) -> LinkWithTagsNew{
+        LinkWithTagsNew{
+            title: Some(title.to_string()),
+            created: Some(Utc::now()),
+            updated: Some(Utc::now()),
+            ..new_link("https://example.com/revision", &["one"])
+        }
+    }
+
+    #[tokio::test]
+    async fn update_and_restore(){
+        let pool = setup().await;
+        let link = Link::create_from_post(&pool, &titled("First"))
+            .await.unwrap();
+        Link::update(&pool, link.id, &titled("Second")).await.unwrap();
+        let revisions = Revision::read_for_link(&pool, link.id).await.unwrap();
+        assert_eq!(revisions.len(), 1);
+        assert_eq!(revisions[0].changes.len(), 1);
+        assert_eq!(revisions[0].changes[0].field, "title");
+        let restored = Revision::restore(&pool, link.id,
+            revisions[0].revision.id).await.unwrap();
+        assert_eq!(restored.title, "First");
+    }
+
+    #[tokio::test]
+    async fn restore_trashed(){
+        let pool = setup().await;
+        let link = Link::create_from_post(&pool, &titled("First"))
+            .await.unwrap();
+        Link::update(&pool, link.id, &titled("Second")).await.unwrap();
+        let revisions = Revision::read_for_link(&pool, link.id).await.unwrap();
+        Link::delete(&pool, link.id).await.unwrap();
+        assert!(Revision::restore(&pool, link.id, revisions[0].revision.id)
+            .await.is_err());
+        // Nothing of the failed restore is kept
+        Link::restore(&pool, link.id).await.unwrap();
+        assert_eq!(Link::read(&pool, link.id).await.unwrap().title, "Second");
+        let revisions = Revision::read_for_link(&pool, link.id).await.unwrap();
+        assert_eq!(revisions.len(), 1);
+    }
+}
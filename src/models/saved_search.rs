@@ -0,0 +1,188 @@
+use serde::{Serialize, Deserialize};
+use chrono::{DateTime, Utc};
+use actix_web::web;
+use sqlx::{sqlite::{SqlitePool, SqliteRow}, Error, query, Row};
+
+use super::link::LinkSearch;
+
+/// Parameters of a search of links kept under a name. With `feed` the
+/// search is also published as a feed.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedSearch {
+    pub id: i64,
+    pub name: String,
+    pub searchterm: Option<String>,
+    pub searchtags: Option<String>,
+    pub visibility: Option<String>,
+    pub sort: Option<String>,
+    pub order: Option<String>,
+    pub feed: bool,
+    pub created: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedSearchNew {
+    pub name: String,
+    pub searchterm: Option<String>,
+    pub searchtags: Option<String>,
+    pub visibility: Option<String>,
+    pub sort: Option<String>,
+    pub order: Option<String>,
+    pub feed: Option<bool>,
+}
+
+/// Pagination of the links of a saved search
+#[derive(Debug, Default, Deserialize)]
+pub struct SavedSearchPage {
+    pub offset: Option<i32>,
+    pub limit: Option<String>,
+    pub after: Option<String>,
+}
+
+impl SavedSearch{
+    fn from_row(row: SqliteRow) -> SavedSearch{
+        SavedSearch {
+            id: row.get("id"),
+            name: row.get("name"),
+            searchterm: row.get("searchterm"),
+            searchtags: row.get("searchtags"),
+            visibility: row.get("visibility"),
+            sort: row.get("sort"),
+            order: row.get("sort_order"),
+            feed: row.get("feed"),
+            created: row.get("created"),
+            updated: row.get("updated"),
+        }
+    }
+
+    pub async fn create(pool: &web::Data<SqlitePool>, saved: &SavedSearchNew)
+            -> Result<SavedSearch, Error>{
+        if saved.name.trim().is_empty(){
+            return Err(Error::Protocol("Empty name".to_string()));
+        }
+        let now = Utc::now();
+        let sql = "INSERT INTO saved_searches (name, searchterm, searchtags,
+                   visibility, sort, sort_order, feed, created, updated)
+                   VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $8) RETURNING *;";
+        query(sql)
+            .bind(saved.name.trim())
+            .bind(&saved.searchterm)
+            .bind(&saved.searchtags)
+            .bind(&saved.visibility)
+            .bind(&saved.sort)
+            .bind(&saved.order)
+            .bind(saved.feed.unwrap_or(false))
+            .bind(now)
+            .map(Self::from_row)
+            .fetch_one(pool.get_ref())
+            .await
+    }
+
+    pub async fn read(pool: &web::Data<SqlitePool>, name: &str)
+            -> Result<SavedSearch, Error>{
+        let sql = "SELECT * FROM saved_searches WHERE name = $1";
+        query(sql)
+            .bind(name)
+            .map(Self::from_row)
+            .fetch_one(pool.get_ref())
+            .await
+    }
+
+    pub async fn read_all(pool: &web::Data<SqlitePool>)
+            -> Result<Vec<SavedSearch>, Error>{
+        let sql = "SELECT * FROM saved_searches ORDER BY name";
+        query(sql)
+            .map(Self::from_row)
+            .fetch_all(pool.get_ref())
+            .await
+    }
+
+    /// Replace the parameters of the saved search, and its name
+    pub async fn update(pool: &web::Data<SqlitePool>, name: &str,
+            saved: &SavedSearchNew) -> Result<SavedSearch, Error>{
+        if saved.name.trim().is_empty(){
+            return Err(Error::Protocol("Empty name".to_string()));
+        }
+        let sql = "UPDATE saved_searches SET name = $1, searchterm = $2,
+                   searchtags = $3, visibility = $4, sort = $5, sort_order = $6,
+                   feed = $7, updated = $8
+                   WHERE name = $9 RETURNING *;";
+        query(sql)
+            .bind(saved.name.trim())
+            .bind(&saved.searchterm)
+            .bind(&saved.searchtags)
+            .bind(&saved.visibility)
+            .bind(&saved.sort)
+            .bind(&saved.order)
+            .bind(saved.feed.unwrap_or(false))
+            .bind(Utc::now())
+            .bind(name)
+            .map(Self::from_row)
+            .fetch_one(pool.get_ref())
+            .await
+    }
+
+    pub async fn delete(pool: &web::Data<SqlitePool>, name: &str)
+            -> Result<bool, Error>{
+        let sql = "DELETE FROM saved_searches WHERE name = $1";
+        let result = query(sql)
+            .bind(name)
+            .execute(pool.get_ref())
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Search of links with the saved parameters and the given page
+    pub fn to_search(&self, page: &SavedSearchPage) -> LinkSearch{
+        LinkSearch{
+            offset: page.offset,
+            limit: page.limit.to_owned(),
+            after: page.after.to_owned(),
+            searchterm: self.searchterm.to_owned(),
+            searchtags: self.searchtags.to_owned(),
+            visibility: self.visibility.to_owned(),
+            sort: self.sort.to_owned(),
+            order: self.order.to_owned(),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SavedSearch, SavedSearchNew, SavedSearchPage};
+    use crate::models::{link::{Link, LinkWithTagsNew},
+        test_utils::{setup, new_link}};
+
+    #[tokio::test]
+    async fn create_and_search(){
+        let pool = setup().await;
+        for (title, private) in [("Rust book", false), ("Rust notes", true),
+                                 ("Go tour", false)]{
+            let new_link = LinkWithTagsNew {
+                title: Some(title.to_string()),
+                private: Some(private),
+                ..new_link(&format!("https://example.com/{}", title), &[])
+            };
+            Link::create_from_post(&pool, &new_link).await.unwrap();
+        }
+        let saved = SavedSearchNew{
+            name: "rust".to_string(),
+            searchterm: Some("Rust".to_string()),
+            searchtags: None,
+            visibility: Some("public".to_string()),
+            sort: Some("title".to_string()),
+            order: None,
+            feed: Some(true),
+        };
+        let saved = SavedSearch::create(&pool, &saved).await.unwrap();
+        assert!(saved.feed);
+        let saved = SavedSearch::read(&pool, "rust").await.unwrap();
+        let search = saved.to_search(&SavedSearchPage::default());
+        let page = Link::search(&pool, &search).await.unwrap().page;
+        assert_eq!(page.total, 1);
+        assert_eq!(page.items[0].title, "Rust book");
+        assert!(SavedSearch::delete(&pool, "rust").await.unwrap());
+    }
+}
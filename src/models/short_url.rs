@@ -3,55 +3,180 @@
 // Licensed under the MIT License (https://opensource.org/licenses/MIT)
 
 /// # ShortURL
-/// Bijective conversion between natural numbers (IDs) (`usize`) and short strings (`String`)
+/// Short codes used as permalinks of the links.
 ///
-/// short_url::encode(usize) takes an ID and turns it into a short string
-/// short_url::decode(String) takes a short string and turns it into an ID
+/// The code is generated once, when the link is created, and stored with it,
+/// so changing the strategy never breaks the permalinks that already exist.
 ///
-/// ## Features
-/// * large alphabet (51 chars) and thus very short resulting strings
-/// * proof against offensive words (removed 'a', 'e', 'i', 'o' and 'u')
-/// * unambiguous (removed 'I', 'l', '1', 'O' and '0')
+/// ## Strategies
+/// * `smallhash` (default): Shaarli's small hash, the URL-safe base64 of the
+///   CRC32 of the creation date and the url (6 chars). Independent of the row
+///   id, so imported links keep the same code.
+/// * `sequential`: bijective conversion between the id of the link and a
+///   short string, shifted by the `SEED` env var. Kept for old databases.
+///
+/// The strategy is selected with the `SHORTURL_STRATEGY` env var.
 ///
 /// ## Example
-/// * 123456789 <=> pgK8p
-use std::env;
+/// * smallhash: 20221120_101732 + https://atareao.es => 6 chars code
+/// * sequential: 10000 <=> 2HU (with SEED 1000)
+use std::{env, fmt::Debug};
+use chrono::{DateTime, Utc};
+use thiserror::Error;
 
-static ALPHABET: &'static str = "0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ-_";
+static ALPHABET: &str = "0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ-_";
 static BASE: usize = 64;
+/// Date format used by Shaarli for the small hash
+static DATE_FORMAT: &str = "%Y%m%d_%H%M%S";
+/// Polynomial of the CRC32 used by PHP's `hash('crc32', ...)`
+static POLYNOMIAL: u32 = 0x04C11DB7;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("empty input")]
+    EmptyInput,
+    #[error("short url out of range")]
+    OutOfRange,
+    #[error("unknown strategy `{0}`")]
+    UnknownStrategy(String),
+}
+
+/// Generates the short code of a link. `attempt` is greater than zero when
+/// the previous code collided with the one of another link.
+pub trait Strategy: Send + Sync + Debug {
+    fn generate(&self, id: i64, url: &str, created: &DateTime<Utc>,
+        attempt: u32) -> Result<String, Error>;
+}
+
+#[derive(Debug)]
+pub struct SmallHash;
+
+impl Strategy for SmallHash {
+    fn generate(&self, _id: i64, url: &str, created: &DateTime<Utc>,
+            attempt: u32) -> Result<String, Error>{
+        if url.is_empty(){
+            return Err(Error::EmptyInput);
+        }
+        let mut text = format!("{}{}", created.format(DATE_FORMAT), url);
+        if attempt > 0 {
+            text.push_str(&attempt.to_string());
+        }
+        Ok(small_hash(&text))
+    }
+}
+
+#[derive(Debug)]
+pub struct Sequential {
+    seed: usize,
+}
+
+impl Sequential {
+    pub fn new(seed: usize) -> Self{
+        Self{ seed }
+    }
+}
+
+impl Strategy for Sequential {
+    fn generate(&self, id: i64, _url: &str, _created: &DateTime<Utc>,
+            attempt: u32) -> Result<String, Error>{
+        // The code is bijective, a collision can not be solved retrying
+        if attempt > 0 {
+            return Err(Error::OutOfRange);
+        }
+        let id = usize::try_from(id).map_err(|_| Error::OutOfRange)?;
+        encode(id, self.seed)
+    }
+}
+
+/// Strategy configured with the `SHORTURL_STRATEGY` env var
+pub fn strategy() -> Result<Box<dyn Strategy>, Error>{
+    let name = env::var("SHORTURL_STRATEGY")
+        .unwrap_or_else(|_| "smallhash".to_string());
+    match name.as_str(){
+        "smallhash" => Ok(Box::new(SmallHash)),
+        "sequential" => Ok(Box::new(Sequential::new(seed()))),
+        _ => Err(Error::UnknownStrategy(name)),
+    }
+}
 
-pub fn encode(mut id: usize) -> String {
-    let seed = match env::var("SEED"){
+fn seed() -> usize{
+    match env::var("SEED"){
         Ok(v) => v.parse::<usize>().unwrap_or(1000),
         Err(_) => 1000,
-    };
-    let mut string: String = format!("");
-    id = id + seed;
+    }
+}
+
+/// Shaarli's `smallHash`: base64 of the CRC32 without padding and with `+`
+/// and `/` replaced by `-` and `_`
+pub fn small_hash(text: &str) -> String {
+    let digest = crc32(text.as_bytes()).to_le_bytes();
+    base64::encode_config(digest, base64::URL_SAFE_NO_PAD)
+}
+
+/// CRC32 as computed by PHP's `hash('crc32', ...)` (the one used by bzip2)
+fn crc32(data: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+    for (i, item) in table.iter_mut().enumerate(){
+        let mut c = (i as u32) << 24;
+        for _ in 0..8 {
+            c = if c & 0x80000000 != 0 { (c << 1) ^ POLYNOMIAL } else { c << 1 };
+        }
+        *item = c;
+    }
+    let mut crc = 0xFFFFFFFFu32;
+    for byte in data {
+        crc = (crc << 8) ^ table[(((crc >> 24) as u8) ^ byte) as usize];
+    }
+    !crc
+}
+
+pub fn encode(id: usize, seed: usize) -> Result<String, Error> {
+    let mut id = id.checked_add(seed).ok_or(Error::OutOfRange)?;
+    let mut string = String::new();
     while id > 0 {
         string.push_str(&ALPHABET[(id % BASE)..(id % BASE + 1)]);
-        id = id / BASE;
+        id /= BASE;
     }
-    string.chars().rev().collect()
+    Ok(string.chars().rev().collect())
 }
 
-pub fn decode(string: &str) -> usize {
-    let mut number: usize = 0;
-    for c in string.chars() {
-        number = number * BASE + ALPHABET.find(c).unwrap();
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[derive(Debug)]
+    enum DecodeError {
+        EmptyInput,
+        InvalidCharacter(char),
+        OutOfRange,
+    }
+
+    /// Inverse of `encode`, to check that the codes round trip
+    fn decode(string: &str, seed: usize) -> Result<usize, DecodeError> {
+        if string.is_empty(){
+            return Err(DecodeError::EmptyInput);
+        }
+        let mut number: usize = 0;
+        for c in string.chars() {
+            let position = ALPHABET.find(c)
+                .ok_or(DecodeError::InvalidCharacter(c))?;
+            number = number.checked_mul(BASE)
+                .and_then(|n| n.checked_add(position))
+                .ok_or(DecodeError::OutOfRange)?;
+        }
+        number.checked_sub(seed).ok_or(DecodeError::OutOfRange)
+    }
+
+    #[test]
+    fn test_short_url(){
+        let value = encode(10000, 1000).unwrap();
+        assert_eq!(value, "2HU");
+        assert_eq!(decode(&value, 1000).unwrap(), 10000);
+        assert!(decode("1", 1000).is_err());
+        assert!(matches!(decode("ASV*asdfe", 1000),
+            Err(DecodeError::InvalidCharacter('*'))));
+        assert!(decode("", 1000).is_err());
+        assert_eq!(crc32(b"123456789"), 0xFC891918);
+        assert_eq!(small_hash("20221120_101732https://atareao.es").len(), 6);
     }
-    let seed = match env::var("SEED"){
-        Ok(v) => v.parse::<usize>().unwrap_or(1000),
-        Err(_) => 1000,
-    };
-    number - seed
-}
-
-#[test]
-fn test_short_url(){
-    let value = encode(10000);
-    println!("10000 => {}", value);
-    let value = encode(10124);
-    println!("10123 => {}", value);
-    let value = decode("ASVasdfe");
-    println!("{}", value);
 }
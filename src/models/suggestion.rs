@@ -0,0 +1,212 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Serialize, Deserialize};
+use actix_web::web;
+use reqwest::Url;
+use sqlx::{sqlite::{SqlitePool, SqliteRow}, Error, query, Row};
+use log::debug;
+
+use super::metatag::Metatag;
+
+/// Minimum score for a suggestion to be applied without asking
+const CONFIDENCE: f64 = 0.5;
+const TITLE_WEIGHT: f64 = 0.5;
+const DESCRIPTION_WEIGHT: f64 = 0.3;
+const KEYWORD_WEIGHT: f64 = 0.4;
+const UNKNOWN_KEYWORD_WEIGHT: f64 = 0.2;
+const DOMAIN_WEIGHT: f64 = 0.6;
+const COOCCURRENCE_WEIGHT: f64 = 0.3;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Suggestion {
+    pub name: String,
+    pub score: f64,
+    pub reasons: Vec<String>,
+}
+
+impl Suggestion{
+    fn new(name: &str) -> Self{
+        Self{
+            name: name.to_string(),
+            score: 0.0,
+            reasons: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, score: f64, reason: &str){
+        self.score = (self.score + score).min(1.0);
+        if !self.reasons.iter().any(|r| r == reason){
+            self.reasons.push(reason.to_string());
+        }
+    }
+
+    pub fn is_confident(&self) -> bool{
+        self.score >= CONFIDENCE
+    }
+
+    /// Suggest tags for `url` ranked by score. Candidates come from the
+    /// existing vocabulary matched against the page metadata, from the tags
+    /// used on other links of the same host and from the tags that usually
+    /// appear together with the previous ones.
+    pub async fn suggest(pool: &web::Data<SqlitePool>, url: &str,
+            metatag: &Metatag) -> Result<Vec<Suggestion>, Error>{
+        let mut suggestions: HashMap<String, Suggestion> = HashMap::new();
+        let vocabulary = Self::read_vocabulary(pool).await?;
+        let title = words(&metatag.title);
+        let description = words(&metatag.description);
+        let keywords: HashSet<String> = metatag.tags.iter()
+            .map(|keyword| keyword.trim().to_lowercase())
+            .filter(|keyword| !keyword.is_empty())
+            .collect();
+        for name in vocabulary.iter(){
+            let lowercase = name.to_lowercase();
+            if contains(&title, &lowercase){
+                suggestions.entry(name.to_string())
+                    .or_insert_with(|| Suggestion::new(name))
+                    .add(TITLE_WEIGHT, "title");
+            }
+            if contains(&description, &lowercase){
+                suggestions.entry(name.to_string())
+                    .or_insert_with(|| Suggestion::new(name))
+                    .add(DESCRIPTION_WEIGHT, "description");
+            }
+            if keywords.contains(&lowercase){
+                suggestions.entry(name.to_string())
+                    .or_insert_with(|| Suggestion::new(name))
+                    .add(KEYWORD_WEIGHT, "keywords");
+            }
+        }
+        let known: HashSet<String> = vocabulary.iter()
+            .map(|name| name.to_lowercase())
+            .collect();
+        for keyword in keywords.iter(){
+            if !known.contains(keyword){
+                suggestions.entry(keyword.to_string())
+                    .or_insert_with(|| Suggestion::new(keyword))
+                    .add(UNKNOWN_KEYWORD_WEIGHT, "keywords");
+            }
+        }
+        if let Some(host) = Url::parse(url).ok()
+                .and_then(|u| u.host_str().map(|h| h.to_lowercase())){
+            for (name, ratio) in Self::read_domain_tags(pool, &host).await?{
+                suggestions.entry(name.to_string())
+                    .or_insert_with(|| Suggestion::new(&name))
+                    .add(DOMAIN_WEIGHT * ratio, "domain");
+            }
+        }
+        let seeds: Vec<String> = suggestions.values()
+            .filter(|suggestion| suggestion.is_confident())
+            .map(|suggestion| suggestion.name.to_string())
+            .collect();
+        for seed in seeds.iter(){
+            for (name, ratio) in Self::read_cooccurrences(pool, seed).await?{
+                suggestions.entry(name.to_string())
+                    .or_insert_with(|| Suggestion::new(&name))
+                    .add(COOCCURRENCE_WEIGHT * ratio, "cooccurrence");
+            }
+        }
+        let mut suggestions: Vec<Suggestion> = suggestions.into_values()
+            .collect();
+        suggestions.sort_by(|a, b| b.score.partial_cmp(&a.score)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.name.cmp(&b.name)));
+        debug!("Suggestions for {}: {:?}", url, suggestions);
+        Ok(suggestions)
+    }
+
+    /// Names of the suggestions that are good enough to be applied
+    pub fn confident(suggestions: &[Suggestion]) -> Vec<String>{
+        suggestions.iter()
+            .filter(|suggestion| suggestion.is_confident())
+            .map(|suggestion| suggestion.name.to_string())
+            .collect()
+    }
+
+    async fn read_vocabulary(pool: &web::Data<SqlitePool>)
+            -> Result<Vec<String>, Error>{
+        let sql = "SELECT name FROM tags";
+        query(sql)
+            .map(|row: SqliteRow| row.get("name"))
+            .fetch_all(pool.get_ref())
+            .await
+    }
+
+    /// Tags used on links of the same host with the ratio of links of that
+    /// host that carry them
+    async fn read_domain_tags(pool: &web::Data<SqlitePool>, host: &str)
+            -> Result<Vec<(String, f64)>, Error>{
+        let sql = "WITH domain AS (
+                        SELECT id FROM links
+                        WHERE url LIKE $1 OR url LIKE $2 OR url LIKE $3)
+                   SELECT t.name name, count(*) occurrences,
+                        (SELECT count(*) FROM domain) total
+                   FROM tags t
+                   INNER JOIN links_tags lt ON t.id = lt.tag_id
+                   INNER JOIN domain d ON d.id = lt.link_id
+                   GROUP BY t.name";
+        query(sql)
+            .bind(format!("%://{}", host))
+            .bind(format!("%://{}/%", host))
+            .bind(format!("%://{}:%", host))
+            .map(|row: SqliteRow| {
+                let occurrences: i64 = row.get("occurrences");
+                let total: i64 = row.get("total");
+                (row.get("name"), occurrences as f64 / total.max(1) as f64)
+            })
+            .fetch_all(pool.get_ref())
+            .await
+    }
+
+    /// Tags that appear together with `name` with the ratio of links tagged
+    /// with `name` that also carry them
+    async fn read_cooccurrences(pool: &web::Data<SqlitePool>, name: &str)
+            -> Result<Vec<(String, f64)>, Error>{
+        let sql = "WITH seed AS (
+                        SELECT lt.link_id FROM links_tags lt
+                        INNER JOIN tags t ON t.id = lt.tag_id
+                        WHERE t.name = $1)
+                   SELECT t.name name, count(*) occurrences,
+                        (SELECT count(*) FROM seed) total
+                   FROM tags t
+                   INNER JOIN links_tags lt ON t.id = lt.tag_id
+                   INNER JOIN seed s ON s.link_id = lt.link_id
+                   WHERE t.name != $1
+                   GROUP BY t.name";
+        query(sql)
+            .bind(name)
+            .map(|row: SqliteRow| {
+                let occurrences: i64 = row.get("occurrences");
+                let total: i64 = row.get("total");
+                (row.get("name"), occurrences as f64 / total.max(1) as f64)
+            })
+            .fetch_all(pool.get_ref())
+            .await
+    }
+}
+
+fn words(text: &str) -> Vec<String>{
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_string())
+        .collect()
+}
+
+/// Check if `tag` appears in `words` as a whole word or, for tags made of
+/// several words, as a sequence of them
+fn contains(words: &[String], tag: &str) -> bool{
+    let parts = self::words(tag);
+    if parts.is_empty(){
+        return false;
+    }
+    words.windows(parts.len()).any(|window| window == parts.as_slice())
+}
+
+#[test]
+fn test_contains(){
+    let title = words("Rust: the Book - Learn Rust programming");
+    assert!(contains(&title, "rust"));
+    assert!(contains(&title, "the book"));
+    assert!(!contains(&title, "rus"));
+    assert!(!contains(&title, "python"));
+}
@@ -0,0 +1,356 @@
+/// # Sync
+/// Keeps a copy of the links in a client up to date.
+///
+/// `changes` returns the links changed after a cursor and the ids of the
+/// ones deleted, as tombstones, with the cursor of the next sync. The
+/// cursor is the last event of the history, so every change recorded there
+/// is seen once. A change of the tags can touch any link, so after one,
+/// without cursor, or with a cursor older than the history kept, every link
+/// is returned with `reset` to replace the copy.
+///
+/// `apply` takes the changes made in the client. Each field is resolved on
+/// its own: the value of the client is kept when its `updated` is after the
+/// last change of that field in the server, known from the revisions.
+use std::collections::HashMap;
+
+use serde::{Serialize, Deserialize};
+use chrono::{DateTime, Utc};
+use actix_web::web;
+use sqlx::{sqlite::{SqlitePool, SqliteRow}, Error, query, Row};
+
+use super::{link::{Link, LinkWithTags, LinkWithTagsNew}, revision::Revision,
+    history::History, page::{self, Bind, Kind}};
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SyncParams {
+    pub since: Option<String>,
+}
+
+/// A link deleted after the cursor
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Tombstone {
+    pub id: i64,
+    pub deleted: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SyncPage {
+    pub links: Vec<LinkWithTags>,
+    pub tombstones: Vec<Tombstone>,
+    pub cursor: String,
+    pub reset: bool,
+}
+
+/// A link created, changed or deleted in the client. Without `id` it is a
+/// new link. Only the fields given are changed.
+#[derive(Debug, Deserialize)]
+pub struct SyncChange {
+    pub id: Option<i64>,
+    pub url: Option<String>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub tags: Option<Vec<String>>,
+    pub private: Option<bool>,
+    pub sticky: Option<bool>,
+    pub deleted: Option<bool>,
+    pub updated: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncStatus {
+    Created,
+    Updated,
+    Deleted,
+    /// Nothing changed, as every field was changed later in the server
+    Unchanged,
+    /// The link is no longer in the server, in the trash or purged
+    Missing,
+    /// The change could not be applied, as told by `message`
+    Failed,
+}
+
+/// Outcome of a change, with the fields where the server won and the link
+/// as it is now, if it was not deleted. Without `id` when a new link could
+/// not be created.
+#[derive(Debug, Serialize)]
+pub struct SyncResult {
+    pub id: Option<i64>,
+    pub status: SyncStatus,
+    pub rejected: Vec<String>,
+    pub link: Option<LinkWithTags>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl SyncResult{
+    fn new(id: i64, status: SyncStatus, rejected: Vec<String>,
+            link: Option<LinkWithTags>) -> Self{
+        Self{ id: Some(id), status, rejected, link, message: None }
+    }
+
+    /// A change that failed, so the others of the batch go on
+    pub fn failed(change: &SyncChange, message: &str) -> Self{
+        Self{
+            id: change.id,
+            status: SyncStatus::Failed,
+            rejected: fields(change),
+            link: None,
+            message: Some(message.to_string()),
+        }
+    }
+}
+
+/// Links changed and deleted after the cursor `since`, every link without it
+pub async fn changes(pool: &web::Data<SqlitePool>, since: &Option<String>)
+        -> Result<SyncPage, Error>{
+    let sql = "SELECT IFNULL(max(id), 0) FROM history";
+    let last: i64 = query(sql)
+        .map(|row: SqliteRow| row.get(0))
+        .fetch_one(pool.get_ref())
+        .await?;
+    let cursor = page::encode_cursor(&[Bind::Int(last).to_value()]);
+    let since = match since{
+        Some(since) => match page::read_cursor(since, &[Kind::Int]).as_deref(){
+            Some([Bind::Int(since)]) => Some(*since),
+            _ => return Err(Error::Protocol("Invalid cursor".to_string())),
+        },
+        None => None,
+    };
+    let since = match (since, History::horizon(pool).await?){
+        (Some(since), Some((last_id, _))) if since < last_id =>
+            return reset(pool, cursor).await,
+        (Some(since), _) => since,
+        (None, _) => return reset(pool, cursor).await,
+    };
+    let sql = "SELECT count(*) FROM history WHERE id > $1 AND id <= $2
+               AND event = 'TAG'";
+    let tags_changed: i64 = query(sql)
+        .bind(since)
+        .bind(last)
+        .map(|row: SqliteRow| row.get(0))
+        .fetch_one(pool.get_ref())
+        .await?;
+    if tags_changed > 0{
+        return reset(pool, cursor).await;
+    }
+    let sql = "SELECT link_id, max(dt) dt FROM history WHERE id > $1
+               AND id <= $2 AND link_id IS NOT NULL
+               GROUP BY link_id ORDER BY link_id";
+    let changed: Vec<(i64, DateTime<Utc>)> = query(sql)
+        .bind(since)
+        .bind(last)
+        .map(|row: SqliteRow| (row.get("link_id"), row.get("dt")))
+        .fetch_all(pool.get_ref())
+        .await?;
+    let mut links = Vec::new();
+    let mut tombstones = Vec::new();
+    for (id, datetime) in changed{
+        // Links in the trash or purged can not be read
+        match Link::read(pool, id).await{
+            Ok(link) => links.push(link),
+            Err(Error::RowNotFound) => tombstones.push(Tombstone{
+                id,
+                deleted: datetime,
+            }),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(SyncPage{ links, tombstones, cursor, reset: false })
+}
+
+async fn reset(pool: &web::Data<SqlitePool>, cursor: String)
+        -> Result<SyncPage, Error>{
+    Ok(SyncPage{
+        links: Link::read_all(pool).await?,
+        tombstones: Vec::new(),
+        cursor,
+        reset: true,
+    })
+}
+
+/// Apply a change of the client. Changes to links no longer in the server
+/// are rejected.
+pub async fn apply(pool: &web::Data<SqlitePool>, change: &SyncChange)
+        -> Result<SyncResult, Error>{
+    let id = match change.id{
+        Some(id) => id,
+        None => return create(pool, change).await,
+    };
+    let link = match Link::read(pool, id).await{
+        Ok(link) => link,
+        Err(Error::RowNotFound) => return Ok(SyncResult::new(id,
+            SyncStatus::Missing, fields(change), None)),
+        Err(e) => return Err(e),
+    };
+    if change.deleted.unwrap_or(false){
+        if change.updated > link.updated{
+            Link::delete(pool, id).await?;
+            return Ok(SyncResult::new(id, SyncStatus::Deleted, Vec::new(),
+                None));
+        }
+        return Ok(SyncResult::new(id, SyncStatus::Unchanged,
+            vec!["deleted".to_string()], Some(link)));
+    }
+    let changed = last_changes(pool, &link).await?;
+    let newer = |field: &str| changed.get(field)
+        .is_none_or(|datetime| change.updated > *datetime);
+    let mut rejected = Vec::new();
+    let mut keep = |field: &str| -> bool{
+        if !newer(field){
+            rejected.push(field.to_string());
+        }
+        newer(field)
+    };
+    let edit = LinkWithTagsNew{
+        url: change.url.to_owned()
+            .filter(|_| keep("url"))
+            .unwrap_or_default(),
+        title: change.title.to_owned().filter(|_| keep("title")),
+        description: change.description.to_owned()
+            .filter(|_| keep("description")),
+        tags: change.tags.to_owned().filter(|_| keep("tags")),
+        private: change.private.filter(|_| keep("private")),
+        sticky: change.sticky.filter(|_| keep("sticky")),
+        created: None,
+        updated: Some(change.updated.max(link.updated)),
+    };
+    if edit.url.is_empty() && edit.title.is_none() && edit.description.is_none()
+            && edit.tags.is_none() && edit.private.is_none()
+            && edit.sticky.is_none(){
+        return Ok(SyncResult::new(id, SyncStatus::Unchanged, rejected,
+            Some(link)));
+    }
+    Link::patch(pool, id, &edit).await?;
+    Ok(SyncResult::new(id, SyncStatus::Updated, rejected,
+        Some(Link::read(pool, id).await?)))
+}
+
+async fn create(pool: &web::Data<SqlitePool>, change: &SyncChange)
+        -> Result<SyncResult, Error>{
+    if change.deleted.unwrap_or(false){
+        return Err(Error::Protocol("Nothing to delete".to_string()));
+    }
+    let new = LinkWithTagsNew{
+        url: change.url.to_owned().unwrap_or_default(),
+        title: change.title.to_owned(),
+        description: change.description.to_owned(),
+        tags: Some(change.tags.to_owned().unwrap_or_default()),
+        private: change.private,
+        sticky: change.sticky,
+        created: Some(change.updated),
+        updated: Some(change.updated),
+    };
+    let link = Link::create_from_post(pool, &new).await?;
+    Ok(SyncResult::new(link.id, SyncStatus::Created, Vec::new(), Some(link)))
+}
+
+/// Names of the fields given in the change
+fn fields(change: &SyncChange) -> Vec<String>{
+    [("url", change.url.is_some()),
+     ("title", change.title.is_some()),
+     ("description", change.description.is_some()),
+     ("tags", change.tags.is_some()),
+     ("private", change.private.is_some()),
+     ("sticky", change.sticky.is_some()),
+     ("deleted", change.deleted.is_some())]
+        .iter()
+        .filter(|(_, given)| *given)
+        .map(|(field, _)| field.to_string())
+        .collect()
+}
+
+/// When each field of the link was last changed. The revisions are the
+/// states before each change, so a field changed at the `updated` of the
+/// state that follows the last revision where it was different. Fields
+/// never changed are missing.
+async fn last_changes(pool: &web::Data<SqlitePool>, link: &LinkWithTags)
+        -> Result<HashMap<String, DateTime<Utc>>, Error>{
+    let revisions = Revision::read_for_link(pool, link.id).await?;
+    let mut changed = HashMap::new();
+    let mut next_updated = link.updated;
+    for revision in revisions.iter(){
+        for change in revision.changes.iter(){
+            changed.entry(change.field.to_string()).or_insert(next_updated);
+        }
+        next_updated = revision.revision.updated;
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+    use super::{changes, apply, SyncChange, SyncStatus};
+    use crate::models::{link::Link, history::History,
+        test_utils::{setup, new_link}};
+
+    fn change(id: Option<i64>, title: Option<&str>, updated: Duration)
+            -> SyncChange{
+        SyncChange{
+            id,
+            url: None,
+            title: title.map(|title| title.to_string()),
+            description: None,
+            tags: None,
+            private: None,
+            sticky: None,
+            deleted: None,
+            updated: Utc::now() + updated,
+        }
+    }
+
+    #[tokio::test]
+    async fn changes_and_tombstones(){
+        let pool = setup().await;
+        let mut ids = Vec::new();
+        for _ in 0..2{
+            let link = Link::create_from_post(&pool, &new_link("", &[]))
+                .await.unwrap();
+            History::created(&pool, link.id).await;
+            ids.push(link.id);
+        }
+        let first = changes(&pool, &None).await.unwrap();
+        assert!(first.reset);
+        assert_eq!(first.links.len(), 2);
+        Link::delete(&pool, ids[1]).await.unwrap();
+        History::deleted(&pool, ids[1]).await;
+        let second = changes(&pool, &Some(first.cursor)).await.unwrap();
+        assert!(!second.reset);
+        assert!(second.links.is_empty());
+        assert_eq!(second.tombstones.len(), 1);
+        assert_eq!(second.tombstones[0].id, ids[1]);
+        let third = changes(&pool, &Some(second.cursor)).await.unwrap();
+        assert!(third.links.is_empty() && third.tombstones.is_empty());
+        assert!(changes(&pool, &Some("invalid".to_string())).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn last_writer_wins(){
+        let pool = setup().await;
+        let created = apply(&pool, &change(None, Some("Offline"),
+            Duration::minutes(-10))).await.unwrap();
+        assert_eq!(created.status, SyncStatus::Created);
+        let id = created.id.unwrap();
+        // Changed in the server after the client
+        let server = change(Some(id), Some("Server"), Duration::minutes(-5));
+        assert_eq!(apply(&pool, &server).await.unwrap().status,
+            SyncStatus::Updated);
+        let mut client = change(Some(id), Some("Client"), Duration::minutes(-7));
+        client.description = Some("From the client".to_string());
+        let result = apply(&pool, &client).await.unwrap();
+        assert_eq!(result.status, SyncStatus::Updated);
+        assert_eq!(result.rejected, vec!["title".to_string()]);
+        let link = result.link.unwrap();
+        assert_eq!(link.title, "Server");
+        assert_eq!(link.description, "From the client");
+        let mut delete = change(Some(id), None, Duration::minutes(1));
+        delete.deleted = Some(true);
+        assert_eq!(apply(&pool, &delete).await.unwrap().status,
+            SyncStatus::Deleted);
+        let late = change(Some(id), Some("Late"), Duration::minutes(2));
+        let result = apply(&pool, &late).await.unwrap();
+        assert_eq!(result.status, SyncStatus::Missing);
+        assert_eq!(result.rejected, vec!["title".to_string()]);
+    }
+}
@@ -1,9 +1,20 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
 use serde::{Serialize, Deserialize};
+use serde_json::Value;
 use actix_web::web;
-use sqlx::{sqlite::{SqlitePool, SqliteRow, SqliteQueryResult}, Error, query, Row};
+use sqlx::{sqlite::{SqlitePool, SqliteRow, SqliteQueryResult, Sqlite},
+    Executor, Transaction, Error, query, Row};
 use log::debug;
 
+use super::{page::{self, Page, Order, Bind, Kind}, alias::Alias,
+    normalization};
 
+/// Separators between the levels of a tag, as in `lang:rust` or
+/// `project/alpha/design`
+pub const SEPARATORS: [char; 2] = [':', '/'];
+/// Tags returned by `complete` when no limit is given
+pub const COMPLETE_LIMIT: i64 = 10;
 
 #[derive(Debug, Serialize, Deserialize, Eq)]
 pub struct Tag {
@@ -13,8 +24,65 @@ pub struct Tag {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TagWithOccurrences {
-    name: String,
-    occurrences: i64,
+    pub name: String,
+    pub description: Option<String>,
+    pub color: Option<String>,
+    pub icon: Option<String>,
+    pub occurrences: i64,
+}
+
+/// Changes to a tag. Fields not given are kept and empty ones are cleared.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TagEdit {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub color: Option<String>,
+    pub icon: Option<String>,
+}
+
+/// Result of renaming a tag: the tag with the new name, whether it was
+/// merged into an existing one and the number of links affected
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TagRename {
+    #[serde(flatten)]
+    pub tag: TagWithOccurrences,
+    pub merged: bool,
+    pub links: i64,
+}
+
+/// A tag found on a set of links: how many of them carry it, how much more
+/// often than expected by chance (`lift`) and the logarithm in base 2 of
+/// that ratio (`pmi`, the pointwise mutual information)
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TagCooccurrence {
+    pub name: String,
+    pub occurrences: i64,
+    pub lift: f64,
+    pub pmi: f64,
+}
+
+/// A tag with its descendants. `occurrences` counts the links with the tag
+/// and `total` the links with the tag or any of its descendants.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TagNode {
+    pub name: String,
+    pub label: String,
+    pub occurrences: i64,
+    pub total: i64,
+    pub children: Vec<TagNode>,
+}
+
+/// Parameters of the search of tags
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TagSearch {
+    pub offset: Option<i32>,
+    pub limit: Option<String>,
+    pub visibility: Option<String>,
+    pub sort: Option<String>,
+    pub order: Option<String>,
+    pub after: Option<String>,
+    pub min_count: Option<i64>,
+    pub tree: Option<bool>,
 }
 
 impl PartialEq for Tag {
@@ -33,6 +101,9 @@ impl Tag{
     fn from_row_with_ocurrences(row: SqliteRow) -> TagWithOccurrences {
         TagWithOccurrences {
             name: row.get("name"),
+            description: row.get("description"),
+            color: row.get("color"),
+            icon: row.get("icon"),
             occurrences: row.get("occurrences"),
         }
     }
@@ -40,7 +111,16 @@ impl Tag{
         row.get("name")
     }
 
+    /// Tag with the normalized name or, if the name is an alias, the tag it
+    /// stands for
     pub async fn get_or_insert(pool: &web::Data<SqlitePool>, name: &str) -> Result<Tag, Error>{
+        let name = &normalization::normalize(name);
+        if name.is_empty(){
+            return Err(Error::Protocol("Empty tag".to_string()));
+        }
+        if let Some(tag) = Alias::resolve(pool.get_ref(), name).await?{
+            return Ok(tag);
+        }
         match Self::read_from_name(pool, name).await {
             Ok(tag) => Ok(tag),
             Err(_) => Self::create(pool, name).await
@@ -56,33 +136,102 @@ impl Tag{
             .await
     }
 
-    pub async fn search(pool: &web::Data<SqlitePool>, option_offset: &Option<i32>, option_limit: &Option<String>, _option_visibility: &Option<String>) -> Result<TagWithOccurrences, Error>{
-        let offset = option_offset.unwrap_or(0);
-        let limit = match option_limit {
-            Some(v) => v.to_owned(),
-            None => "20".to_string(),
+    /// Tags with the number of links, out of the trash, that use them,
+    /// restricted to public, private or all links by `visibility`.
+    /// Sorted by `name` or by `usage` (the most used first) in pages of
+    /// `limit` tags, starting after the cursor `after` or, without it, after
+    /// `offset` tags. Tags used less than `min_count` times are left out.
+    pub async fn search(pool: &web::Data<SqlitePool>, search: &TagSearch)
+            -> Result<Page<TagWithOccurrences>, Error>{
+        let usage = match search.sort.as_deref(){
+            None | Some("name") => false,
+            Some("usage") => true,
+            Some(other) => return Err(Error::Protocol(
+                format!("Invalid sort {}", other))),
+        };
+        let order = match (&search.order, usage){
+            (None, true) => Order::Desc,
+            _ => Order::from_param(&search.order),
+        };
+        let limit = page::limit(&search.limit);
+        let mut binds = Vec::new();
+        let visibility = match search.visibility.as_deref(){
+            Some("all") | None => "",
+            Some(value) => {
+                binds.push(Bind::Int(("private" == value).into()));
+                "AND l.private = ?"
+            },
         };
-        let mut sql = Vec::new();
-        sql.push("SELECT t.name name, count(*) occurrences FROM tags t
+        let occurrences = format!("SELECT t.name name,
+                        t.description description, t.color color, t.icon icon,
+                        count(l.id) occurrences
+                   FROM tags t
                    LEFT JOIN links_tags lt ON t.id = lt.tag_id
-                   GROUP BY t.name
-                   ORDER BY t.name".to_string());
-        sql.push(if limit != "all"{
-            format!("LIMIT {} OFFSET {}", limit, offset)
+                   LEFT JOIN links l ON l.id = lt.link_id
+                        AND l.deleted_at IS NULL {}
+                   GROUP BY t.id", visibility);
+        let mut conditions = vec!["occurrences >= ?".to_string()];
+        binds.push(Bind::Int(search.min_count.unwrap_or(0)));
+        let sql = format!("SELECT count(*) FROM ({}) WHERE {}", occurrences,
+            conditions.join(" AND "));
+        let total: i64 = page::bind_all(query(&sql), &binds)
+            .map(|row: SqliteRow| row.get(0))
+            .fetch_one(pool.get_ref())
+            .await?;
+        let columns = if usage{
+            vec![("occurrences", order), ("name", Order::Asc)]
         }else{
-            "".to_string()
-        });
-        debug!("{}", &sql.join(" "));
-        query(&sql.join(" "))
+            vec![("name", order)]
+        };
+        let kinds = if usage{ vec![Kind::Int, Kind::Text] }else{ vec![Kind::Text] };
+        let mut offset = search.offset.unwrap_or(0).into();
+        if let Some(after) = &search.after{
+            let values = page::read_cursor(after, &kinds)
+                .ok_or_else(|| Error::Protocol("Invalid cursor".to_string()))?;
+            let (condition, after_binds) = page::after_condition(&columns,
+                &values);
+            conditions.push(condition);
+            binds.extend(after_binds);
+            offset = 0;
+        }
+        let sql = format!("SELECT * FROM ({})
+                   WHERE {}
+                   ORDER BY {}
+                   LIMIT ? OFFSET ?", occurrences, conditions.join(" AND "),
+            columns.iter()
+                .map(|(column, order)| format!("{} {}", column, order.sql()))
+                .collect::<Vec<String>>()
+                .join(", "));
+        binds.push(Bind::Int(limit.unwrap_or(-1)));
+        binds.push(Bind::Int(offset));
+        debug!("{}", &sql);
+        let items = page::bind_all(query(&sql), &binds)
             .map(Self::from_row_with_ocurrences)
-            .fetch_one(pool.get_ref())
-            .await
+            .fetch_all(pool.get_ref())
+            .await?;
+        let next = match (items.last(), limit){
+            (Some(last), Some(limit)) if items.len() as i64 == limit => {
+                let mut values = Vec::new();
+                if usage{
+                    values.push(Value::from(last.occurrences));
+                }
+                values.push(Value::from(last.name.to_string()));
+                Some(page::encode_cursor(&values))
+            },
+            _ => None,
+        };
+        Ok(Page{ items, total, next })
     }
+
     pub async fn read(pool: &web::Data<SqlitePool>, name: &str) -> Result<TagWithOccurrences, Error>{
-        let sql = "SELECT t.name name, count(*) occurrences FROM tags t
+        let sql = "SELECT t.name name, t.description description,
+                        t.color color, t.icon icon, count(l.id) occurrences
+                   FROM tags t
                    LEFT JOIN links_tags lt ON t.id = lt.tag_id
+                   LEFT JOIN links l ON l.id = lt.link_id
+                        AND l.deleted_at IS NULL
                    WHERE t.name = $1
-                   GROUP BY t.name";
+                   GROUP BY t.id";
         query(sql)
             .bind(name)
             .map(Self::from_row_with_ocurrences)
@@ -118,6 +267,25 @@ impl Tag{
             .await
     }
     
+    /// Tags of the link with their description, color and icon
+    pub async fn read_details_for_link(pool: &web::Data<SqlitePool>, link_id: i64)
+            -> Result<Vec<TagWithOccurrences>, Error>{
+        let sql = "SELECT t.name name, t.description description,
+                        t.color color, t.icon icon, count(l.id) occurrences
+                   FROM tags t
+                   LEFT JOIN links_tags lt ON t.id = lt.tag_id
+                   LEFT JOIN links l ON l.id = lt.link_id
+                        AND l.deleted_at IS NULL
+                   WHERE t.id IN (SELECT tag_id FROM links_tags WHERE link_id = $1)
+                   GROUP BY t.id
+                   ORDER BY t.name";
+        query(sql)
+            .bind(link_id)
+            .map(Self::from_row_with_ocurrences)
+            .fetch_all(pool.get_ref())
+            .await
+    }
+
     pub async fn delete(pool: &web::Data<SqlitePool>, name: &str) -> Result<Tag, Error>{
         let sql = "DELETE FROM tags WHERE name = $1 RETURNING *;";
         query(sql)
@@ -127,17 +295,298 @@ impl Tag{
             .await
     }
 
-    pub async fn update(pool: &web::Data<SqlitePool>, name: &str, new_name: &str) -> Result<TagWithOccurrences, Error>{
-        let sql = "UPDATE tags SET name = $1 WHERE name = $2 RETURNING *;";
-        match query(sql)
-            .bind(new_name)
+    /// Rename the tag and its descendants, so renaming `lang` renames
+    /// `lang:rust` to `language:rust`. A tag renamed to the name of an
+    /// existing one is merged into it, as Shaarli does.
+    pub async fn update(pool: &web::Data<SqlitePool>, name: &str, new_name: &str) -> Result<TagRename, Error>{
+        let edit = TagEdit{
+            name: Some(new_name.to_string()),
+            ..Default::default()
+        };
+        Self::edit(pool, name, &edit).await
+    }
+
+    /// Rename the tag, as `update` does, and change its description, color
+    /// and icon
+    pub async fn edit(pool: &web::Data<SqlitePool>, name: &str, edit: &TagEdit)
+            -> Result<TagRename, Error>{
+        if let Some(color) = &edit.color{
+            if !color.is_empty() && !is_color(color){
+                return Err(Error::Protocol(format!("Invalid color {}", color)));
+            }
+        }
+        let new_name = match &edit.name{
+            Some(new_name) => normalization::normalize(new_name),
+            None => name.to_string(),
+        };
+        if new_name.is_empty(){
+            return Err(Error::Protocol("Empty tag".to_string()));
+        }
+        let new_name = new_name.as_str();
+        let mut tx = pool.begin().await?;
+        let sql = "SELECT * FROM tags WHERE name = $1";
+        let tag = query(sql)
             .bind(name)
             .map(Self::from_row)
-            .fetch_one(pool.get_ref())
-            .await{
-                Ok(_) => Self::read(pool, new_name).await,
-                Err(e) => Err(e),
+            .fetch_one(&mut tx)
+            .await?;
+        let sql = format!("SELECT * FROM tags WHERE {}",
+            vec!["name LIKE ? ESCAPE '\\'"; SEPARATORS.len()].join(" OR "));
+        let mut descendants = query(&sql);
+        for pattern in descendants_patterns(name){
+            descendants = descendants.bind(pattern);
+        }
+        let descendants = descendants
+            .map(Self::from_row)
+            .fetch_all(&mut tx)
+            .await?;
+        let mut merged = false;
+        let mut links = HashSet::new();
+        for tag in std::iter::once(tag).chain(descendants){
+            let target = format!("{}{}", new_name, &tag.name[name.len()..]);
+            let (tag_merged, tag_links) = Self::rename(&mut tx, &tag, &target)
+                .await?;
+            merged |= tag_merged;
+            links.extend(tag_links);
+        }
+        let sql = "UPDATE tags SET
+                        description = IIF($1 IS NULL, description, NULLIF($1, '')),
+                        color = IIF($2 IS NULL, color, NULLIF($2, '')),
+                        icon = IIF($3 IS NULL, icon, NULLIF($3, ''))
+                   WHERE name = $4";
+        query(sql)
+            .bind(&edit.description)
+            .bind(&edit.color)
+            .bind(&edit.icon)
+            .bind(new_name)
+            .execute(&mut tx)
+            .await?;
+        tx.commit().await?;
+        Ok(TagRename{
+            tag: Self::read(pool, new_name).await?,
+            merged,
+            links: links.len() as i64,
+        })
+    }
+
+    /// Give the tag the new name or, if another tag already has it, move the
+    /// links to that tag. Returns whether it was merged and the links moved.
+    async fn rename(tx: &mut Transaction<'_, Sqlite>, tag: &Tag, new_name: &str)
+            -> Result<(bool, Vec<i64>), Error>{
+        let sql = "SELECT link_id FROM links_tags WHERE tag_id = $1";
+        let links = query(sql)
+            .bind(tag.id)
+            .map(|row: SqliteRow| row.get("link_id"))
+            .fetch_all(&mut *tx)
+            .await?;
+        let sql = "SELECT * FROM tags WHERE name = $1";
+        let target = query(sql)
+            .bind(new_name)
+            .map(Self::from_row)
+            .fetch_optional(&mut *tx)
+            .await?;
+        match target{
+            Some(target) if target.id == tag.id => Ok((false, Vec::new())),
+            Some(target) => {
+                debug!("Merge tag {} into {}", tag.name, target.name);
+                let sql = "UPDATE OR IGNORE links_tags SET tag_id = $1
+                           WHERE tag_id = $2";
+                query(sql).bind(target.id).bind(tag.id).execute(&mut *tx).await?;
+                // Links that already had both tags
+                let sql = "DELETE FROM links_tags WHERE tag_id = $1";
+                query(sql).bind(tag.id).execute(&mut *tx).await?;
+                let sql = "UPDATE tag_aliases SET tag_id = $1 WHERE tag_id = $2";
+                query(sql).bind(target.id).bind(tag.id).execute(&mut *tx).await?;
+                let sql = "DELETE FROM tags WHERE id = $1";
+                query(sql).bind(tag.id).execute(&mut *tx).await?;
+                Ok((true, links))
+            },
+            None => {
+                let sql = "UPDATE tags SET name = $1 WHERE id = $2";
+                query(sql).bind(new_name).bind(tag.id).execute(&mut *tx).await?;
+                Ok((false, links))
+            },
+        }
+    }
+
+    /// Tags starting with `prefix`, without case, followed by the ones with
+    /// a level starting with it, as `lang:rust` for `ru`. Each group sorted
+    /// by usage and by the date of the last link tagged with them.
+    ///
+    /// Only the first group goes through the index, the levels are matched
+    /// with `LIKE '%:ru%'` over every tag, so the prefix can not be empty and
+    /// the results are always limited.
+    pub async fn complete(pool: &web::Data<SqlitePool>, prefix: &str,
+            limit: i64) -> Result<Vec<TagWithOccurrences>, Error>{
+        if prefix.trim().is_empty(){
+            return Err(Error::Protocol("Empty prefix".to_string()));
+        }
+        // Bounds of the prefix so the search goes through the NOCASE index
+        let mut binds = vec![
+            Bind::Text(prefix.to_string()),
+            Bind::Text(format!("{}{}", prefix, char::MAX)),
+        ];
+        let levels: Vec<String> = SEPARATORS.iter()
+            .map(|separator| {
+                binds.push(Bind::Text(format!("%{}{}%", separator,
+                    escape_like(prefix))));
+                "name LIKE ? ESCAPE '\\'".to_string()
+            })
+            .collect();
+        let sql = format!("WITH matches AS (
+                        SELECT id, min(rank) rank FROM (
+                            SELECT id, 0 rank FROM tags
+                            WHERE name >= ? COLLATE NOCASE
+                                AND name < ? COLLATE NOCASE
+                            UNION ALL
+                            SELECT id, 1 rank FROM tags WHERE {})
+                        GROUP BY id)
+                   SELECT t.name name, t.description description,
+                        t.color color, t.icon icon, count(l.id) occurrences
+                   FROM matches m
+                   INNER JOIN tags t ON t.id = m.id
+                   LEFT JOIN links_tags lt ON t.id = lt.tag_id
+                   LEFT JOIN links l ON l.id = lt.link_id
+                        AND l.deleted_at IS NULL
+                   GROUP BY t.id
+                   ORDER BY min(m.rank), occurrences DESC, max(l.created) DESC,
+                        t.name
+                   LIMIT ?", levels.join(" OR "));
+        binds.push(Bind::Int(limit));
+        page::bind_all(query(&sql), &binds)
+            .map(Self::from_row_with_ocurrences)
+            .fetch_all(pool.get_ref())
+            .await
+    }
+
+    /// Tags that appear on the links with the tag `name`
+    pub async fn related(pool: &web::Data<SqlitePool>, name: &str,
+            limit: Option<i64>) -> Result<Vec<TagCooccurrence>, Error>{
+        Self::read_from_name(pool, name).await?;
+        let subset = "SELECT lt.link_id id FROM links_tags lt
+                      INNER JOIN tags t ON t.id = lt.tag_id
+                      INNER JOIN links l ON l.id = lt.link_id
+                      WHERE t.name = ? AND l.deleted_at IS NULL";
+        Self::cooccurrences(pool, subset, &[Bind::Text(name.to_string())],
+            Some(name), limit).await
+    }
+
+    /// Tags on the links returned by `subset`, a query with a single `id`
+    /// column and positional parameters, compared with their use over all
+    /// the links out of the trash. The most frequent first.
+    pub async fn cooccurrences(pool: &web::Data<SqlitePool>, subset: &str,
+            binds: &[Bind], exclude: Option<&str>, limit: Option<i64>)
+            -> Result<Vec<TagCooccurrence>, Error>{
+        let sql = format!("WITH live AS (
+                        SELECT id FROM links WHERE deleted_at IS NULL),
+                   subset AS ({}),
+                   usage AS (
+                        SELECT lt.tag_id tag_id, count(*) n FROM links_tags lt
+                        INNER JOIN live ON live.id = lt.link_id
+                        GROUP BY lt.tag_id)
+                   SELECT t.name name, count(*) occurrences, u.n usage,
+                        (SELECT count(*) FROM subset) subset_total,
+                        (SELECT count(*) FROM live) total
+                   FROM links_tags lt
+                   INNER JOIN subset s ON s.id = lt.link_id
+                   INNER JOIN tags t ON t.id = lt.tag_id
+                   INNER JOIN usage u ON u.tag_id = t.id
+                   WHERE t.name != ?
+                   GROUP BY t.id
+                   ORDER BY occurrences DESC, t.name ASC
+                   LIMIT ?", subset);
+        let mut binds = binds.to_vec();
+        binds.push(Bind::Text(exclude.unwrap_or_default().to_string()));
+        binds.push(Bind::Int(limit.unwrap_or(-1)));
+        page::bind_all(query(&sql), &binds)
+            .map(|row: SqliteRow| {
+                let occurrences: i64 = row.get("occurrences");
+                let usage: i64 = row.get("usage");
+                let subset_total: i64 = row.get("subset_total");
+                let total: i64 = row.get("total");
+                let lift = (occurrences * total) as f64 /
+                    (subset_total * usage).max(1) as f64;
+                TagCooccurrence{
+                    name: row.get("name"),
+                    occurrences,
+                    lift,
+                    pmi: lift.log2(),
+                }
+            })
+            .fetch_all(pool.get_ref())
+            .await
+    }
+
+    /// Apply the normalization rules to the tags already stored, merging the
+    /// ones that end up with the same name. Returns the tags changed.
+    pub async fn normalize_existing(pool: &web::Data<SqlitePool>)
+            -> Result<usize, Error>{
+        let mut names: Vec<String> = Self::read_all(pool).await?
+            .into_iter()
+            .map(|tag| tag.name)
+            .collect();
+        // Descendants go first, so renaming a tag never cascades over them
+        names.sort_by_key(|name| std::cmp::Reverse(name.chars().count()));
+        let mut changed = 0;
+        for name in names{
+            let normalized = normalization::normalize(&name);
+            if normalized == name || normalized.is_empty(){
+                continue;
+            }
+            debug!("Normalize tag {} to {}", name, normalized);
+            Self::update(pool, &name, &normalized).await?;
+            changed += 1;
+        }
+        Ok(changed)
+    }
+
+    /// Delete the tags no link uses, except the ones with aliases or with
+    /// a description, color or icon, that someone took the time to curate
+    pub async fn collect_garbage<'c, E>(executor: E) -> Result<u64, Error>
+            where E: Executor<'c, Database = Sqlite>{
+        let sql = "DELETE FROM tags
+                   WHERE id NOT IN (SELECT tag_id FROM links_tags)
+                   AND id NOT IN (SELECT tag_id FROM tag_aliases)
+                   AND description IS NULL AND color IS NULL AND icon IS NULL";
+        Ok(query(sql)
+            .execute(executor)
+            .await?
+            .rows_affected())
+    }
+
+    /// Tags arranged by levels. Levels without a tag of their own, like
+    /// `project` for `project/alpha`, are included with no occurrences.
+    pub async fn tree(pool: &web::Data<SqlitePool>) -> Result<Vec<TagNode>, Error>{
+        let sql = "SELECT t.name name, l.id link_id FROM tags t
+                   LEFT JOIN links_tags lt ON t.id = lt.tag_id
+                   LEFT JOIN links l ON l.id = lt.link_id
+                        AND l.deleted_at IS NULL";
+        let rows: Vec<(String, Option<i64>)> = query(sql)
+            .map(|row: SqliteRow| (row.get("name"), row.get("link_id")))
+            .fetch_all(pool.get_ref())
+            .await?;
+        let mut links: BTreeMap<String, HashSet<i64>> = BTreeMap::new();
+        for (name, link_id) in rows{
+            let mut ancestor = parent(&name);
+            while let Some(name) = ancestor{
+                links.entry(name.to_string()).or_default();
+                ancestor = parent(name);
+            }
+            let entry = links.entry(name.to_string()).or_default();
+            if let Some(link_id) = link_id{
+                entry.insert(link_id);
+            }
+        }
+        // Children of every level, the top ones under `None`, in one pass
+        let mut children: HashMap<Option<&str>, Vec<&str>> = HashMap::new();
+        for name in links.keys(){
+            children.entry(parent(name)).or_default().push(name);
         }
+        Ok(children.get(&None)
+            .map(|roots| roots.iter()
+                .map(|name| build_node(name, &links, &children).0)
+                .collect())
+            .unwrap_or_default())
     }
 
     pub async fn drop(pool: &web::Data<SqlitePool>) -> Result<SqliteQueryResult, Error>{
@@ -148,42 +597,71 @@ impl Tag{
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use std::{env, path::Path};
-    use sqlx::{Sqlite, Pool, sqlite::SqlitePoolOptions, migrate::{Migrator,
-        MigrateDatabase}};
-    use actix_web::web::Data;
-    use super::Tag;
-    use dotenv::dotenv;
+/// Name of the level above the tag, if any
+pub fn parent(name: &str) -> Option<&str>{
+    name.rfind(SEPARATORS).map(|position| &name[..position])
+}
 
-    async fn setup() -> Data<Pool<Sqlite>>{
-        dotenv().ok();
-        let db_url = env::var("DATABASE_URL").expect("DATABASE_URL not set");
-        if !Sqlite::database_exists(&db_url).await.unwrap(){
-            Sqlite::create_database(&db_url).await.unwrap()
-        }
-        let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
-        let migrations = Path::new(&crate_dir).join("migrations");
-        println!("{}", migrations.to_str().unwrap());
+/// Patterns to find the descendants of the tag with `LIKE ... ESCAPE '\'`
+pub fn descendants_patterns(name: &str) -> Vec<String>{
+    SEPARATORS.iter()
+        .map(|separator| format!("{}{}%", escape_like(name), separator))
+        .collect()
+}
 
-    let pool = SqlitePoolOptions::new()
-        .max_connections(1)
-        .connect(&db_url)
-        .await
-        .expect("pool failed");
+/// Colors as `#rgb` or `#rrggbb`
+fn is_color(value: &str) -> bool{
+    match value.strip_prefix('#'){
+        Some(hex) => (hex.len() == 3 || hex.len() == 6) &&
+            hex.chars().all(|c| c.is_ascii_hexdigit()),
+        None => false,
+    }
+}
 
-    Migrator::new(migrations)
-        .await.unwrap()
-        .run(&pool)
-        .await.unwrap();
+pub fn escape_like(value: &str) -> String{
+    value.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
 
-        Data::new(pool)
+fn build_node(name: &str, links: &BTreeMap<String, HashSet<i64>>,
+        children: &HashMap<Option<&str>, Vec<&str>>) -> (TagNode, HashSet<i64>){
+    let own = links.get(name).cloned().unwrap_or_default();
+    let mut all = own.clone();
+    let mut nodes = Vec::new();
+    for child in children.get(&Some(name)).into_iter().flatten(){
+        let (node, child_links) = build_node(child, links, children);
+        all.extend(child_links);
+        nodes.push(node);
     }
+    let label = match parent(name){
+        Some(parent) => name[parent.len() + 1..].to_string(),
+        None => name.to_string(),
+    };
+    (TagNode{
+        name: name.to_string(),
+        label,
+        occurrences: own.len() as i64,
+        total: all.len() as i64,
+        children: nodes,
+    }, all)
+}
 
-    async fn teardown(pool: &Data<Pool<Sqlite>>){
-        let _result = Tag::drop(pool).await;
-    }
+#[test]
+fn test_parent(){
+    assert_eq!(parent("lang:rust"), Some("lang"));
+    assert_eq!(parent("project/alpha/design"), Some("project/alpha"));
+    assert_eq!(parent("rust"), None);
+    assert_eq!(escape_like("a_b%"), "a\\_b\\%");
+    assert!(is_color("#1a2B3c"));
+    assert!(!is_color("red"));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::{link::{Link, LinkWithTagsNew},
+        test_utils::{setup, new_link}};
+    use super::{Tag, TagSearch, TagEdit};
 
     #[tokio::test]
     async fn create(){
@@ -194,7 +672,6 @@ mod tests {
             },
             Err(_) => assert!(false),
         }
-        teardown(&pool).await;
     }
 
     #[tokio::test]
@@ -207,7 +684,6 @@ mod tests {
             },
             Err(_) => assert!(false),
         }
-        teardown(&pool).await;
     }
 
     #[tokio::test]
@@ -218,7 +694,6 @@ mod tests {
         let _ = Tag::create(&pool, "etiqueta 3").await;
         let tags = Tag::read_all(&pool).await.unwrap();
         assert_eq!(tags.len(), 3);
-        teardown(&pool).await;
     }
 
     #[tokio::test]
@@ -228,6 +703,152 @@ mod tests {
         let _ = Tag::delete(&pool, &tag.name).await;
         let tags = Tag::read_all(&pool).await.unwrap();
         assert_eq!(tags.len(), 0);
-        teardown(&pool).await;
+    }
+
+    #[tokio::test]
+    async fn rename_with_children(){
+        let pool = setup().await;
+        let _ = Tag::create(&pool, "lang").await;
+        let _ = Tag::create(&pool, "lang:rust").await;
+        let _ = Tag::create(&pool, "lang/web/css").await;
+        let _ = Tag::create(&pool, "language").await;
+        Tag::update(&pool, "lang", "code").await.unwrap();
+        assert!(Tag::read(&pool, "code:rust").await.is_ok());
+        assert!(Tag::read(&pool, "code/web/css").await.is_ok());
+        assert!(Tag::read(&pool, "language").await.is_ok());
+        let tree = Tag::tree(&pool).await.unwrap();
+        let code = tree.iter().find(|node| node.name == "code").unwrap();
+        assert_eq!(code.children.len(), 2);
+        let web = code.children.iter().find(|node| node.label == "web").unwrap();
+        assert_eq!(web.name, "code/web");
+        assert_eq!(web.children[0].label, "css");
+    }
+
+    #[tokio::test]
+    async fn rename_merging(){
+        let pool = setup().await;
+        Link::create_from_post(&pool, &new_link("https://example.com/1",
+            &["rust", "rustlang"])).await.unwrap();
+        Link::create_from_post(&pool, &new_link("https://example.com/2",
+            &["rustlang", "rustlang:async"])).await.unwrap();
+        Link::create_from_post(&pool, &new_link("https://example.com/3",
+            &["rust:async"])).await.unwrap();
+        let rename = Tag::update(&pool, "rustlang", "rust").await.unwrap();
+        assert!(rename.merged);
+        assert_eq!(rename.links, 2);
+        assert_eq!(rename.tag.occurrences, 2);
+        assert!(Tag::read_from_name(&pool, "rustlang").await.is_err());
+        assert!(Tag::read_from_name(&pool, "rustlang:async").await.is_err());
+        assert_eq!(Tag::read(&pool, "rust:async").await.unwrap().occurrences, 2);
+    }
+
+    #[tokio::test]
+    async fn search(){
+        let pool = setup().await;
+        Link::create_from_post(&pool, &new_link("https://example.com/1",
+            &["rust", "web"])).await.unwrap();
+        let private = LinkWithTagsNew{
+            private: Some(true),
+            ..new_link("https://example.com/private", &["rust"])
+        };
+        Link::create_from_post(&pool, &private).await.unwrap();
+        let _ = Tag::create(&pool, "unused").await;
+        let search = TagSearch{
+            sort: Some("usage".to_string()),
+            ..Default::default()
+        };
+        let page = Tag::search(&pool, &search).await.unwrap();
+        assert_eq!(page.total, 3);
+        assert_eq!(page.items[0].name, "rust");
+        assert_eq!(page.items[0].occurrences, 2);
+        assert_eq!(page.items[2].occurrences, 0);
+        let search = TagSearch{
+            visibility: Some("public".to_string()),
+            min_count: Some(1),
+            limit: Some("1".to_string()),
+            ..Default::default()
+        };
+        let page = Tag::search(&pool, &search).await.unwrap();
+        assert_eq!(page.total, 2);
+        assert_eq!(page.items[0].occurrences, 1);
+        let search = TagSearch{ after: page.next, ..search };
+        let page = Tag::search(&pool, &search).await.unwrap();
+        assert_eq!(page.items[0].name, "web");
+    }
+
+    #[tokio::test]
+    async fn related(){
+        let pool = setup().await;
+        Link::create_from_post(&pool, &new_link("https://example.com/1",
+            &["rust", "web"])).await.unwrap();
+        Link::create_from_post(&pool, &new_link("https://example.com/2",
+            &["rust", "cli"])).await.unwrap();
+        Link::create_from_post(&pool, &new_link("https://example.com/3",
+            &["web"])).await.unwrap();
+        Link::create_from_post(&pool, &new_link("https://example.com/4",
+            &["cli"])).await.unwrap();
+        let related = Tag::related(&pool, "rust", None).await.unwrap();
+        assert_eq!(related.len(), 2);
+        assert!(related.iter().all(|tag| tag.occurrences == 1));
+        assert!((related[0].lift - 1.0).abs() < f64::EPSILON);
+        assert!(Tag::related(&pool, "missing", None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn edit_metadata(){
+        let pool = setup().await;
+        let _ = Tag::create(&pool, "rust").await;
+        let edit = TagEdit{
+            description: Some("Systems programming".to_string()),
+            color: Some("#dea584".to_string()),
+            icon: Some("🦀".to_string()),
+            ..Default::default()
+        };
+        let tag = Tag::edit(&pool, "rust", &edit).await.unwrap().tag;
+        assert_eq!(tag.color.as_deref(), Some("#dea584"));
+        let edit = TagEdit{
+            name: Some("rustlang".to_string()),
+            color: Some("".to_string()),
+            ..Default::default()
+        };
+        let tag = Tag::edit(&pool, "rust", &edit).await.unwrap().tag;
+        assert_eq!(tag.name, "rustlang");
+        assert_eq!(tag.icon.as_deref(), Some("🦀"));
+        assert!(tag.color.is_none());
+        let edit = TagEdit{
+            color: Some("red".to_string()),
+            ..Default::default()
+        };
+        assert!(Tag::edit(&pool, "rustlang", &edit).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn complete(){
+        let pool = setup().await;
+        Link::create_from_post(&pool, &new_link("https://example.com/1",
+            &["Ruby", "lang:rust"])).await.unwrap();
+        Link::create_from_post(&pool, &new_link("https://example.com/2",
+            &["lang:rust"])).await.unwrap();
+        let _ = Tag::create(&pool, "rust").await;
+        let _ = Tag::create(&pool, "trust").await;
+        let names: Vec<String> = Tag::complete(&pool, "ru", 10).await.unwrap()
+            .into_iter()
+            .map(|tag| tag.name)
+            .collect();
+        assert_eq!(names, vec!["Ruby", "rust", "lang:rust"]);
+        assert_eq!(Tag::complete(&pool, "RU", 1).await.unwrap().len(), 1);
+        assert!(Tag::complete(&pool, " ", 10).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn normalize_existing(){
+        let pool = setup().await;
+        Link::create_from_post(&pool, &new_link("https://example.com/1",
+            &["science-fiction"])).await.unwrap();
+        let _ = Tag::create(&pool, "science fiction").await;
+        let _ = Tag::create(&pool, "lang:web dev").await;
+        assert_eq!(Tag::normalize_existing(&pool).await.unwrap(), 2);
+        assert!(Tag::read_from_name(&pool, "science fiction").await.is_err());
+        assert!(Tag::read_from_name(&pool, "lang:web-dev").await.is_ok());
     }
 }
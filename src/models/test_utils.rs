@@ -0,0 +1,45 @@
+use std::{path::Path, str::FromStr};
+use sqlx::{SqlitePool, migrate::Migrator, sqlite::{SqliteConnectOptions,
+    SqlitePoolOptions}};
+use actix_web::web::Data;
+use dotenv::dotenv;
+
+use super::link::LinkWithTagsNew;
+
+/// Pool over a new database in memory with the migrations run, so every
+/// test works over its own data
+pub async fn setup() -> Data<SqlitePool>{
+    dotenv().ok();
+    let options = SqliteConnectOptions::from_str("sqlite::memory:")
+        .unwrap()
+        .foreign_keys(true);
+    // The database lives as long as its only connection
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect_with(options)
+        .await
+        .expect("pool failed");
+    let migrations = Path::new(env!("CARGO_MANIFEST_DIR")).join("migrations");
+    Migrator::new(migrations)
+        .await.unwrap()
+        .run(&pool)
+        .await.unwrap();
+    Data::new(pool)
+}
+
+/// A public link with the given tags. It has a title, so notes need no
+/// page to read it from.
+pub fn new_link(url: &str, tags: &[&str]) -> LinkWithTagsNew{
+    LinkWithTagsNew {
+        url: url.to_string(),
+        title: Some("Link".to_string()),
+        description: Some("".to_string()),
+        tags: Some(tags.iter().map(|tag| tag.to_string()).collect()),
+        private: Some(false),
+        sticky: None,
+        created: None,
+        updated: None,
+    }
+}
@@ -0,0 +1,428 @@
+use std::time::Duration;
+
+use serde::{Serialize, Deserialize};
+use serde_json::json;
+use chrono::{DateTime, Utc};
+use actix_web::web;
+use sqlx::{sqlite::{SqlitePool, SqliteRow, SqliteQueryResult}, Error, query, Row};
+use reqwest::{Client, Url, header::CONTENT_TYPE};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tokio::sync::broadcast::error::RecvError;
+use log::{debug, error};
+
+use super::{history::History, link::Link};
+
+/// Events of the links a webhook can be subscribed to
+const EVENTS: [&str; 3] = ["CREATED", "UPDATED", "DELETED"];
+/// Attempts of each delivery before giving up
+const MAX_ATTEMPTS: i64 = 5;
+/// Delay before the second attempt, doubled on each of the next ones
+const BACKOFF: Duration = Duration::from_secs(10);
+/// Deliveries failed in a row that disable the webhook
+const MAX_FAILURES: i64 = 5;
+
+/// A URL notified with a POST of every change of the links. With `events`
+/// only the changes of those types are notified, and with `secret` the
+/// body is signed in the header `X-Webhook-Signature`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    pub id: i64,
+    pub url: String,
+    pub events: Option<String>,
+    #[serde(skip_serializing)]
+    pub secret: Option<String>,
+    pub active: bool,
+    pub failures: i64,
+    pub created: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WebhookNew {
+    pub url: String,
+    /// Types of the events separated by commas, as `CREATED,DELETED`
+    pub events: Option<String>,
+    pub secret: Option<String>,
+    pub active: Option<bool>,
+}
+
+/// An attempt to notify an event to a webhook, with the HTTP status of the
+/// response or the error
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Delivery {
+    pub id: i64,
+    pub webhook_id: i64,
+    pub history_id: i64,
+    pub attempt: i64,
+    pub status: Option<i64>,
+    pub error: Option<String>,
+    pub datetime: DateTime<Utc>,
+}
+
+impl Delivery{
+    fn from_row(row: SqliteRow) -> Delivery{
+        Delivery {
+            id: row.get("id"),
+            webhook_id: row.get("webhook_id"),
+            history_id: row.get("history_id"),
+            attempt: row.get("attempt"),
+            status: row.get("status"),
+            error: row.get("error"),
+            datetime: row.get("dt"),
+        }
+    }
+}
+
+impl Webhook{
+    fn from_row(row: SqliteRow) -> Webhook{
+        Webhook {
+            id: row.get("id"),
+            url: row.get("url"),
+            events: row.get("events"),
+            secret: row.get("secret"),
+            active: row.get("active"),
+            failures: row.get("failures"),
+            created: row.get("created"),
+            updated: row.get("updated"),
+        }
+    }
+
+    /// Url and events of the webhook, checked
+    fn validate(webhook: &WebhookNew) -> Result<(String, Option<String>), Error>{
+        match Url::parse(webhook.url.trim()){
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => (),
+            _ => return Err(Error::Protocol("Invalid url".to_string())),
+        }
+        let events: Vec<String> = webhook.events.as_deref()
+            .unwrap_or("")
+            .split(',')
+            .map(|event| event.trim().to_uppercase())
+            .filter(|event| !event.is_empty())
+            .collect();
+        if events.iter().any(|event| !EVENTS.contains(&event.as_str())){
+            return Err(Error::Protocol("Invalid events".to_string()));
+        }
+        let events = if events.is_empty(){
+            None
+        }else{
+            Some(events.join(","))
+        };
+        Ok((webhook.url.trim().to_string(), events))
+    }
+
+    pub async fn create(pool: &web::Data<SqlitePool>, webhook: &WebhookNew)
+            -> Result<Webhook, Error>{
+        let (url, events) = Self::validate(webhook)?;
+        let sql = "INSERT INTO webhooks (url, events, secret, active, created,
+                   updated) VALUES ($1, $2, $3, $4, $5, $5) RETURNING *;";
+        query(sql)
+            .bind(url)
+            .bind(events)
+            .bind(&webhook.secret)
+            .bind(webhook.active.unwrap_or(true))
+            .bind(Utc::now())
+            .map(Self::from_row)
+            .fetch_one(pool.get_ref())
+            .await
+    }
+
+    pub async fn read(pool: &web::Data<SqlitePool>, id: i64)
+            -> Result<Webhook, Error>{
+        let sql = "SELECT * FROM webhooks WHERE id = $1";
+        query(sql)
+            .bind(id)
+            .map(Self::from_row)
+            .fetch_one(pool.get_ref())
+            .await
+    }
+
+    pub async fn read_all(pool: &web::Data<SqlitePool>)
+            -> Result<Vec<Webhook>, Error>{
+        let sql = "SELECT * FROM webhooks ORDER BY id";
+        query(sql)
+            .map(Self::from_row)
+            .fetch_all(pool.get_ref())
+            .await
+    }
+
+    /// Replace the webhook. Enabling it again clears its failures.
+    pub async fn update(pool: &web::Data<SqlitePool>, id: i64,
+            webhook: &WebhookNew) -> Result<Webhook, Error>{
+        let (url, events) = Self::validate(webhook)?;
+        let sql = "UPDATE webhooks SET url = $1, events = $2, secret = $3,
+                   active = $4, failures = IIF($4, 0, failures), updated = $5
+                   WHERE id = $6 RETURNING *;";
+        query(sql)
+            .bind(url)
+            .bind(events)
+            .bind(&webhook.secret)
+            .bind(webhook.active.unwrap_or(true))
+            .bind(Utc::now())
+            .bind(id)
+            .map(Self::from_row)
+            .fetch_one(pool.get_ref())
+            .await
+    }
+
+    pub async fn delete(pool: &web::Data<SqlitePool>, id: i64)
+            -> Result<bool, Error>{
+        let sql = "DELETE FROM webhooks WHERE id = $1";
+        let result = query(sql)
+            .bind(id)
+            .execute(pool.get_ref())
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Latest deliveries of the webhook, the newest first
+    pub async fn deliveries(pool: &web::Data<SqlitePool>, id: i64, limit: i64)
+            -> Result<Vec<Delivery>, Error>{
+        let sql = "SELECT * FROM webhook_deliveries WHERE webhook_id = $1
+                   ORDER BY id DESC LIMIT $2";
+        query(sql)
+            .bind(id)
+            .bind(limit)
+            .map(Delivery::from_row)
+            .fetch_all(pool.get_ref())
+            .await
+    }
+
+    /// Whether the webhook is subscribed to the event
+    pub fn matches(&self, event: &str) -> bool{
+        match &self.events{
+            Some(events) => events.split(',').any(|e| e == event),
+            None => EVENTS.contains(&event),
+        }
+    }
+
+    /// Signature of the body with the secret, as `sha256=<hex digest>`
+    pub fn sign(secret: &str, body: &str) -> String{
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+            .expect("HMAC accepts keys of any size");
+        mac.update(body.as_bytes());
+        format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+    }
+
+    /// Notify every change of the links written to the history to the
+    /// webhooks subscribed to it, until the server stops
+    pub fn start(pool: web::Data<SqlitePool>){
+        let mut receiver = History::subscribe();
+        tokio::spawn(async move {
+            let client = Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .unwrap_or_default();
+            let mut last_id: Option<i64> = None;
+            loop{
+                let items = match receiver.recv().await{
+                    Ok(item) => vec![item],
+                    // The events lost by the channel are read from the history
+                    Err(RecvError::Lagged(lost)) => match last_id{
+                        Some(id) => match History::read_after(&pool, id).await{
+                            Ok(items) => items,
+                            Err(e) => {
+                                error!("Webhooks behind, {} events lost: {}",
+                                    lost, e);
+                                continue;
+                            },
+                        },
+                        None => {
+                            error!("Webhooks behind, {} events lost", lost);
+                            continue;
+                        },
+                    },
+                    Err(RecvError::Closed) => break,
+                };
+                for item in items{
+                    // Already handled before the channel lagged
+                    if last_id.is_some_and(|id| item.id <= id){
+                        continue;
+                    }
+                    last_id = Some(item.id);
+                    Self::dispatch(&pool, &client, &item).await;
+                }
+            }
+        });
+    }
+
+    /// Deliver the event to the webhooks subscribed to it
+    async fn dispatch(pool: &web::Data<SqlitePool>, client: &Client,
+            item: &History){
+        let link_id = match item.link_id{
+            Some(link_id) if EVENTS.contains(&item.event.as_str()) => link_id,
+            _ => return,
+        };
+        let webhooks = match Self::subscribed(pool, &item.event).await{
+            Ok(webhooks) if !webhooks.is_empty() => webhooks,
+            Ok(_) => return,
+            Err(e) => {
+                error!("Can not read webhooks: {}", e);
+                return;
+            },
+        };
+        let body = json!({
+            "id": item.id,
+            "event": &item.event,
+            "datetime": item.datetime,
+            "link_id": link_id,
+            "payload": &item.payload,
+            "link": Link::read(pool, link_id).await.ok(),
+        }).to_string();
+        for webhook in webhooks{
+            let pool = pool.clone();
+            let client = client.clone();
+            let body = body.clone();
+            let event = item.event.clone();
+            let history_id = item.id;
+            tokio::spawn(async move {
+                webhook.deliver(&pool, &client, &event, history_id, &body)
+                    .await;
+            });
+        }
+    }
+
+    async fn subscribed(pool: &web::Data<SqlitePool>, event: &str)
+            -> Result<Vec<Webhook>, Error>{
+        let sql = "SELECT * FROM webhooks WHERE active = 1";
+        Ok(query(sql)
+            .map(Self::from_row)
+            .fetch_all(pool.get_ref())
+            .await?
+            .into_iter()
+            .filter(|webhook| webhook.matches(event))
+            .collect())
+    }
+
+    /// Post the body until it is accepted, waiting longer after each failed
+    /// attempt. Every attempt is logged.
+    async fn deliver(&self, pool: &web::Data<SqlitePool>, client: &Client,
+            event: &str, history_id: i64, body: &str){
+        for attempt in 1..=MAX_ATTEMPTS{
+            let mut request = client.post(&self.url)
+                .header(CONTENT_TYPE, "application/json")
+                .header("X-Webhook-Event", event)
+                .header("X-Webhook-Delivery", history_id.to_string())
+                .body(body.to_string());
+            if let Some(secret) = &self.secret{
+                request = request.header("X-Webhook-Signature",
+                    Self::sign(secret, body));
+            }
+            let (status, failure) = match request.send().await{
+                Ok(response) if response.status().is_success() =>
+                    (Some(response.status().as_u16() as i64), None),
+                Ok(response) => (Some(response.status().as_u16() as i64),
+                    Some(format!("Status {}", response.status()))),
+                Err(e) => (None, Some(e.to_string())),
+            };
+            let succeeded = failure.is_none();
+            if let Err(e) = self.log(pool, history_id, attempt, status, failure)
+                    .await{
+                error!("Can not log delivery: {}", e);
+            }
+            if succeeded{
+                debug!("Event {} delivered to {}", history_id, self.url);
+                if let Err(e) = self.succeeded(pool).await{
+                    error!("Can not update webhook {}: {}", self.id, e);
+                }
+                return;
+            }
+            if attempt < MAX_ATTEMPTS{
+                tokio::time::sleep(BACKOFF * 2u32.pow(attempt as u32 - 1)).await;
+            }
+        }
+        error!("Can not deliver event {} to {}", history_id, self.url);
+        if let Err(e) = self.failed(pool).await{
+            error!("Can not update webhook {}: {}", self.id, e);
+        }
+    }
+
+    async fn log(&self, pool: &web::Data<SqlitePool>, history_id: i64,
+            attempt: i64, status: Option<i64>, failure: Option<String>)
+            -> Result<SqliteQueryResult, Error>{
+        let sql = "INSERT INTO webhook_deliveries (webhook_id, history_id,
+                   attempt, status, error, dt) VALUES ($1, $2, $3, $4, $5, $6)";
+        query(sql)
+            .bind(self.id)
+            .bind(history_id)
+            .bind(attempt)
+            .bind(status)
+            .bind(failure)
+            .bind(Utc::now())
+            .execute(pool.get_ref())
+            .await
+    }
+
+    async fn succeeded(&self, pool: &web::Data<SqlitePool>)
+            -> Result<SqliteQueryResult, Error>{
+        let sql = "UPDATE webhooks SET failures = 0 WHERE id = $1";
+        query(sql)
+            .bind(self.id)
+            .execute(pool.get_ref())
+            .await
+    }
+
+    /// Count a failed delivery, disabling the webhook after too many in a
+    /// row
+    async fn failed(&self, pool: &web::Data<SqlitePool>)
+            -> Result<Webhook, Error>{
+        let sql = "UPDATE webhooks SET failures = failures + 1,
+                   active = IIF(failures + 1 >= $1, 0, active)
+                   WHERE id = $2 RETURNING *;";
+        query(sql)
+            .bind(MAX_FAILURES)
+            .bind(self.id)
+            .map(Self::from_row)
+            .fetch_one(pool.get_ref())
+            .await
+    }
+}
+
+#[test]
+fn test_sign(){
+    // RFC 4231, test case 2
+    assert_eq!(Webhook::sign("Jefe", "what do ya want for nothing?"),
+        "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::test_utils::setup;
+    use super::{Webhook, WebhookNew, MAX_FAILURES};
+
+    #[tokio::test]
+    async fn events_and_failures(){
+        let pool = setup().await;
+        let new = WebhookNew{
+            url: "http://localhost:9/hook".to_string(),
+            events: Some("created, deleted".to_string()),
+            secret: Some("secret".to_string()),
+            active: None,
+        };
+        let webhook = Webhook::create(&pool, &new).await.unwrap();
+        assert_eq!(webhook.events, Some("CREATED,DELETED".to_string()));
+        assert!(webhook.matches("CREATED"));
+        assert!(!webhook.matches("UPDATED"));
+        let invalid = WebhookNew{
+            url: "ftp://localhost/hook".to_string(),
+            events: None,
+            secret: None,
+            active: None,
+        };
+        assert!(Webhook::create(&pool, &invalid).await.is_err());
+        for _ in 0..MAX_FAILURES{
+            webhook.log(&pool, 1, 1, None, Some("refused".to_string()))
+                .await.unwrap();
+            webhook.failed(&pool).await.unwrap();
+        }
+        let webhook = Webhook::read(&pool, webhook.id).await.unwrap();
+        assert!(!webhook.active);
+        assert_eq!(webhook.failures, MAX_FAILURES);
+        let deliveries = Webhook::deliveries(&pool, webhook.id, 10).await.unwrap();
+        assert_eq!(deliveries.len() as i64, MAX_FAILURES);
+        let webhook = Webhook::update(&pool, webhook.id, &new).await.unwrap();
+        assert!(webhook.active);
+        assert_eq!(webhook.failures, 0);
+        assert!(Webhook::delete(&pool, webhook.id).await.unwrap());
+    }
+}
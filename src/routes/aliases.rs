@@ -0,0 +1,67 @@
+use actix_web::{get, post, put, delete, web, HttpResponse};
+use serde_json::json;
+use sqlx::SqlitePool;
+use log::debug;
+
+use crate::models::alias::{Alias, AliasNew};
+
+fn error(e: sqlx::Error) -> HttpResponse{
+    match e{
+        sqlx::Error::RowNotFound => HttpResponse::NotFound().json(
+            json!({"code": 404, "message": "Alias not found"})),
+        sqlx::Error::Database(_) => HttpResponse::Conflict().json(
+            json!({"code": 409, "message": "Alias already exists"})),
+        e => HttpResponse::BadRequest().json(
+            json!({"code": 400, "message": e.to_string()})),
+    }
+}
+
+#[get("/aliases")]
+pub async fn read_all(pool: web::Data<SqlitePool>) -> HttpResponse{
+    debug!("Action: Read. Path: /aliases");
+    match Alias::read_all(&pool).await{
+        Ok(items) => HttpResponse::Ok().json(items),
+        Err(e) => error(e),
+    }
+}
+
+#[post("/aliases")]
+pub async fn create(pool: web::Data<SqlitePool>, alias: web::Json<AliasNew>
+) -> HttpResponse{
+    debug!("Action: Create. Path: /aliases");
+    match Alias::create(&pool, &alias).await{
+        Ok(item) => HttpResponse::Created().json(item),
+        Err(e) => error(e),
+    }
+}
+
+#[get("/aliases/{alias}")]
+pub async fn read(pool: web::Data<SqlitePool>, alias: web::Path<String>
+) -> HttpResponse{
+    debug!("Action: Read. Path: /aliases/{}", alias);
+    match Alias::read(&pool, &alias).await{
+        Ok(item) => HttpResponse::Ok().json(item),
+        Err(e) => error(e),
+    }
+}
+
+#[put("/aliases/{alias}")]
+pub async fn update(pool: web::Data<SqlitePool>, alias: web::Path<String>,
+        new_alias: web::Json<AliasNew>) -> HttpResponse{
+    debug!("Action: Update. Path: /aliases/{}", alias);
+    match Alias::update(&pool, &alias, &new_alias).await{
+        Ok(item) => HttpResponse::Ok().json(item),
+        Err(e) => error(e),
+    }
+}
+
+#[delete("/aliases/{alias}")]
+pub async fn delete(pool: web::Data<SqlitePool>, alias: web::Path<String>
+) -> HttpResponse{
+    debug!("Action: Delete. Path: /aliases/{}", alias);
+    match Alias::delete(&pool, &alias).await{
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => error(sqlx::Error::RowNotFound),
+        Err(e) => error(e),
+    }
+}
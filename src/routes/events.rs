@@ -0,0 +1,93 @@
+use std::time::Duration;
+
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder, Either};
+use actix_web_lab::sse;
+use serde_json::json;
+use sqlx::SqlitePool;
+use tokio::sync::broadcast::error::RecvError;
+use log::{debug, error};
+
+use crate::models::{claim::authorize, history::History};
+
+fn to_event(history: &History) -> Option<sse::Data>{
+    match sse::Data::new_json(history){
+        Ok(data) => Some(data.event(history.event.to_owned())
+            .id(history.id.to_string())),
+        Err(e) => {
+            error!("Can not serialize event {}: {}", history.id, e);
+            None
+        },
+    }
+}
+
+/// Send the events not sent yet. False when the client is gone.
+async fn send(sender: &sse::Sender, items: &[History], last_id: &mut Option<i64>)
+        -> bool{
+    for item in items.iter(){
+        if last_id.is_some_and(|id| item.id <= id){
+            continue;
+        }
+        if let Some(data) = to_event(item){
+            if sender.send(data).await.is_err(){
+                return false;
+            }
+        }
+        *last_id = Some(item.id);
+    }
+    true
+}
+
+/// Stream of the changes as they are written to the history. With the
+/// header `Last-Event-ID` the events after that one are sent first.
+#[get("/events")]
+pub async fn stream(req: HttpRequest, pool: web::Data<SqlitePool>,
+        secret: web::Data<String>) -> Either<HttpResponse, impl Responder>{
+    debug!("Action: Stream. Path: /events");
+    if authorize(req.headers(), &secret).is_err(){
+        return Either::Left(HttpResponse::Unauthorized().json(
+            json!({"code": 401, "message": "Unauthorized"})));
+    }
+    let last_id = req.headers()
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<i64>().ok());
+    // Subscribe before reading the history so no event is lost in between
+    let mut receiver = History::subscribe();
+    let (sender, events) = sse::channel(10);
+    actix_web::rt::spawn(async move {
+        let mut last_id = last_id;
+        if let Some(id) = last_id{
+            match History::read_after(&pool, id).await{
+                Ok(items) => if !send(&sender, &items, &mut last_id).await{
+                    return;
+                },
+                Err(e) => error!("Can not read history: {}", e),
+            }
+        }
+        loop{
+            let items = match receiver.recv().await{
+                Ok(item) => vec![item],
+                // The events lost by the channel are read from the history
+                Err(RecvError::Lagged(lost)) => match last_id{
+                    Some(id) => match History::read_after(&pool, id).await{
+                        Ok(items) => items,
+                        Err(e) => {
+                            error!("Stream behind, {} events lost: {}", lost, e);
+                            continue;
+                        },
+                    },
+                    None => {
+                        error!("Stream behind, {} events lost", lost);
+                        continue;
+                    },
+                },
+                Err(RecvError::Closed) => break,
+            };
+            if !send(&sender, &items, &mut last_id).await{
+                break;
+            }
+        }
+        debug!("Stream closed");
+    });
+    Either::Right(events.with_keep_alive(Duration::from_secs(15)))
+}
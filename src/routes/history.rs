@@ -2,28 +2,28 @@ use actix_web::{get, post, put, delete, web, error::{ErrorNotFound,
     ErrorConflict, ErrorUnauthorized}, Error, HttpResponse};
 use serde_json::json;
 use sqlx::{SqlitePool, error::Error::Database};
-use serde::Deserialize;
+use actix_web::http::header::{HeaderName, HeaderValue};
 use log::debug;
-use crate::models::history::History;
-
-#[derive(Debug, Deserialize)]
-struct Params{
-    pub offset: Option<i32>,
-    pub limit: Option<String>,
-    pub since: Option<String>,
-}
-
+use crate::models::history::{History, HistorySearch};
+use super::paginated;
 
 #[get("/history")]
-pub async fn search(pool: web::Data<SqlitePool>, params: web::Query<Params>
+pub async fn search(pool: web::Data<SqlitePool>, params: web::Query<HistorySearch>
 ) -> HttpResponse{
-    debug!("Action: Search. Path: /tags");
-    let offset = &params.offset;
-    let limit = &params.limit;
-    let since = &params.since;
-    match History::search(&pool, since, offset, limit)
+    debug!("Action: Search. Path: /history");
+    match History::search(&pool, &params)
         .await{
-            Ok(items) => HttpResponse::Ok().json(items),
+            Ok(history) => {
+                let mut response = paginated(history.page);
+                // Some changes after `since` are gone, the client has to
+                // read everything again
+                if history.expired{
+                    response.headers_mut().insert(
+                        HeaderName::from_static("x-history-expired"),
+                        HeaderValue::from_static("true"));
+                }
+                response
+            },
             Err(_) => HttpResponse::BadRequest().json(
                 json!({"code": 400, "message": "Invalid parameters"})),
         }
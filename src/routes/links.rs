@@ -1,27 +1,56 @@
 use actix_web::{get, post, put, delete, web, error::{ErrorNotFound,
-    ErrorConflict, ErrorUnauthorized}, Error, HttpResponse};
+    ErrorConflict, ErrorUnauthorized}, Error, HttpRequest, HttpResponse};
 use serde_json::json;
 use sqlx::{SqlitePool, error::Error::Database};
 use serde::Deserialize;
 use log::debug;
 
-use crate::models::{link::{LinkWithTagsNew, Link}, history::History};
+use crate::models::{link::{LinkWithTagsNew, Link, LinkSearch, LinkPage},
+    history::History, claim::authorize, bulk::{Bulk, Operation}};
+use super::{paginated, page_headers};
 #[derive(Debug, Deserialize)]
-struct Params{
-    pub offset: Option<i32>,
-    pub limit: Option<String>,
-    pub searchterm: Option<String>,
-    pub searchtags: Option<String>,
-    pub visibility: Option<String>,
+struct CreateParams{
+    pub merge: Option<bool>,
 }
 
-
 #[post("/links")]
-pub async fn create(pool: web::Data<SqlitePool>, new_link_with_tags: web::Json<LinkWithTagsNew>) -> HttpResponse{
+pub async fn create(pool: web::Data<SqlitePool>, params: web::Query<CreateParams>, new_link_with_tags: web::Json<LinkWithTagsNew>) -> HttpResponse{
     debug!("Action: Post. Path: /links");
+    // Notes have no url to be duplicated
+    let existing = if new_link_with_tags.url.trim().is_empty(){
+        None
+    }else{
+        Link::read_from_canonical(&pool, &new_link_with_tags.url).await.ok()
+    };
+    if let Some(existing) = existing{
+        if !params.merge.unwrap_or(false){
+            let message = match existing.deleted_at{
+                Some(_) => "Link already exists in the trash",
+                None => "Link already exists",
+            };
+            return HttpResponse::Conflict().json(
+                json!({"code": 409, "message": message, "link": existing}));
+        }
+        if existing.deleted_at.is_some(){
+            if let Err(e) = Link::restore(&pool, existing.id).await{
+                return HttpResponse::BadRequest().json(
+                    json!({"code": 400, "message": e.to_string()}));
+            }
+        }
+        let tags = new_link_with_tags.tags.to_owned().unwrap_or_default();
+        return match Link::merge_tags(&pool, existing.id, &tags).await{
+            Ok(item) => {
+                History::updated_with(&pool, item.id, json!({"merged": true}))
+                    .await;
+                HttpResponse::Ok().json(item)
+            },
+            Err(e) => HttpResponse::BadRequest().json(
+                json!({"code": 400, "message": e.to_string()})),
+        };
+    }
     let result = match Link::create_from_post(&pool, &new_link_with_tags).await{
         Ok(item) => {
-            History::created(&pool).await;
+            History::created(&pool, item.id).await;
             HttpResponse::Created().json(item)
         },
         Err(e) => {
@@ -38,18 +67,41 @@ pub async fn create(pool: web::Data<SqlitePool>, new_link_with_tags: web::Json<L
     result
 }
 
+#[post("/links/bulk")]
+pub async fn bulk(pool: web::Data<SqlitePool>, bulk: web::Json<Bulk>) -> HttpResponse{
+    debug!("Action: Bulk. Path: /links/bulk");
+    if bulk.ids.is_none() && bulk.query.is_none(){
+        return HttpResponse::BadRequest().json(
+            json!({"code": 400, "message": "Either ids or query is required"}));
+    }
+    match bulk.execute(&pool).await{
+        Ok(report) => {
+            for item in report.items.iter().filter(|item| item.success){
+                debug!("Bulk {:?} over {}", report.operation, item.id);
+                let payload = json!({"operation": report.operation});
+                if report.operation == Operation::Delete{
+                    History::deleted_with(&pool, item.id, payload).await;
+                }else{
+                    History::updated_with(&pool, item.id, payload).await;
+                }
+            }
+            HttpResponse::Ok().json(report)
+        },
+        Err(e) => HttpResponse::BadRequest().json(
+            json!({"code": 400, "message": e.to_string()})),
+    }
+}
+
 #[get("/links")]
-pub async fn read(pool: web::Data<SqlitePool>, params: web::Query<Params>
+pub async fn read(pool: web::Data<SqlitePool>, params: web::Query<LinkSearch>
 ) -> HttpResponse{
     debug!("Action: Get. Path: /links");
-    let offset = &params.offset;
-    let limit = &params.limit;
-    let searchterm = &params.searchterm;
-    let searchtags = &params.searchtags;
-    let visibility = &params.visibility;
-    match Link::search(&pool, offset, limit, searchterm, searchtags, visibility)
+    match Link::search(&pool, &params)
         .await{
-            Ok(items) => HttpResponse::Ok().json(items),
+            Ok(LinkPage{ page, facets: None }) => paginated(page),
+            // The links come along with the counts of their tags
+            Ok(LinkPage{ page, facets: Some(facets) }) => page_headers(&page)
+                .json(json!({"items": page.items, "facets": facets})),
             Err(_) => HttpResponse::BadRequest().json(
                 json!({"code": 400, "message": "Invalid parameters"})),
         }
@@ -65,15 +117,60 @@ pub async fn read_one(pool: web::Data<SqlitePool>, link_id: web::Path<i64>,
     }
 }
 
+#[get("/links/by-shorturl/{shorturl}")]
+pub async fn read_from_shorturl(req: HttpRequest, pool: web::Data<SqlitePool>, secret: web::Data<String>, shorturl: web::Path<String>,
+) -> HttpResponse{
+    debug!("Path: /links/by-shorturl/{}", shorturl);
+    match Link::read_from_shorturl(&pool, &shorturl).await{
+        Ok(item) => {
+            if item.private && authorize(req.headers(), &secret).is_err(){
+                HttpResponse::Unauthorized().json(
+                    json!({"code": 401, "message": "Unauthorized"}))
+            }else{
+                HttpResponse::Ok().json(item)
+            }
+        },
+        Err(_) => HttpResponse::NotFound().json(
+            json!({"code": 404, "message": "Not found"})),
+    }
+}
+
 #[put("/links/{link_id}")]
 pub async fn update(pool: web::Data<SqlitePool>, link_id: web::Path<i64>, link_with_tags: web::Json<LinkWithTagsNew>
 ) -> HttpResponse {
     debug!("Action: Put. Path: /links");
     match Link::update(&pool, link_id.into_inner(), &link_with_tags).await{
         Ok(item) => {
-            History::updated(&pool).await;
+            History::updated(&pool, item.id).await;
+            HttpResponse::Ok().json(item)
+        },
+        Err(_) => HttpResponse::BadRequest().finish(),
+    }
+}
+
+#[post("/links/{link_id}/pin")]
+pub async fn pin(pool: web::Data<SqlitePool>, link_id: web::Path<i64>,
+) -> HttpResponse {
+    debug!("Action: Pin. Path: /links/{link_id}/pin");
+    set_sticky(&pool, link_id.into_inner(), true).await
+}
+
+#[delete("/links/{link_id}/pin")]
+pub async fn unpin(pool: web::Data<SqlitePool>, link_id: web::Path<i64>,
+) -> HttpResponse {
+    debug!("Action: Unpin. Path: /links/{link_id}/pin");
+    set_sticky(&pool, link_id.into_inner(), false).await
+}
+
+async fn set_sticky(pool: &web::Data<SqlitePool>, link_id: i64, sticky: bool) -> HttpResponse {
+    match Link::pin(pool, link_id, sticky).await{
+        Ok(item) => {
+            History::updated_with(pool, link_id, json!({"sticky": sticky}))
+                .await;
             HttpResponse::Ok().json(item)
         },
+        Err(sqlx::Error::RowNotFound) => HttpResponse::NotFound().json(
+            json!({"code": 404, "message": "Not found"})),
         Err(_) => HttpResponse::BadRequest().finish(),
     }
 }
@@ -82,9 +179,12 @@ pub async fn update(pool: web::Data<SqlitePool>, link_id: web::Path<i64>, link_w
 pub async fn delete(pool: web::Data<SqlitePool>, link_id: web::Path<i64>,
 ) -> HttpResponse {
     debug!("Action: Delete. Path: /links/{link_id}");
-    match Link::delete(&pool, link_id.into_inner()).await {
-        Ok(_) => {
-            History::deleted(&pool).await;
+    let link_id = link_id.into_inner();
+    match Link::delete(&pool, link_id).await {
+        Ok(false) => HttpResponse::NotFound().json(
+            json!({"code": 404, "message": "Not found"})),
+        Ok(true) => {
+            History::deleted(&pool, link_id).await;
             HttpResponse::NoContent().finish()
         },
         Err(_) => HttpResponse::BadRequest().finish(),
@@ -1,4 +1,34 @@
+use actix_web::{HttpResponse, HttpResponseBuilder};
+use serde::Serialize;
+
+use crate::models::page::Page;
+
 pub mod links;
 pub mod tags;
 pub mod general;
 pub mod history;
+pub mod shaare;
+pub mod revisions;
+pub mod trash;
+pub mod aliases;
+pub mod saved;
+pub mod events;
+pub mod webhooks;
+pub mod sync;
+
+/// Items of the page with the total and the cursor of the next page in the
+/// headers, so the body keeps the format of the Shaarli API
+pub fn paginated<T: Serialize>(page: Page<T>) -> HttpResponse{
+    page_headers(&page).json(page.items)
+}
+
+/// Response with the total and the cursor of the next page in the headers,
+/// for bodies other than the bare items
+pub fn page_headers<T>(page: &Page<T>) -> HttpResponseBuilder{
+    let mut response = HttpResponse::Ok();
+    response.insert_header(("X-Total-Count", page.total.to_string()));
+    if let Some(next) = &page.next{
+        response.insert_header(("X-Next-Cursor", next.as_str()));
+    }
+    response
+}
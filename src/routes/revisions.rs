@@ -0,0 +1,38 @@
+use actix_web::{get, post, web, HttpResponse};
+use serde_json::json;
+use sqlx::SqlitePool;
+use log::debug;
+
+use crate::models::{revision::Revision, history::History};
+
+#[get("/links/{link_id}/revisions")]
+pub async fn read(pool: web::Data<SqlitePool>, link_id: web::Path<i64>,
+) -> HttpResponse{
+    debug!("Action: Read. Path: /links/{}/revisions", link_id);
+    match Revision::read_for_link(&pool, link_id.into_inner()).await{
+        Ok(items) => HttpResponse::Ok().json(items),
+        Err(sqlx::Error::RowNotFound) => HttpResponse::NotFound().json(
+            json!({"code": 404, "message": "Not found"})),
+        Err(_) => HttpResponse::BadRequest().json(
+            json!({"code": 400, "message": "Invalid parameters"})),
+    }
+}
+
+#[post("/links/{link_id}/revisions/{revision_id}/restore")]
+pub async fn restore(pool: web::Data<SqlitePool>, path: web::Path<(i64, i64)>,
+) -> HttpResponse{
+    let (link_id, revision_id) = path.into_inner();
+    debug!("Action: Restore. Path: /links/{}/revisions/{}/restore", link_id,
+        revision_id);
+    match Revision::restore(&pool, link_id, revision_id).await{
+        Ok(item) => {
+            History::updated_with(&pool, link_id,
+                json!({"revision": revision_id})).await;
+            HttpResponse::Ok().json(item)
+        },
+        Err(sqlx::Error::RowNotFound) => HttpResponse::NotFound().json(
+            json!({"code": 404, "message": "Not found"})),
+        Err(e) => HttpResponse::BadRequest().json(
+            json!({"code": 400, "message": e.to_string()})),
+    }
+}
@@ -0,0 +1,162 @@
+use actix_web::{get, post, put, delete, web, HttpRequest, HttpResponse};
+use serde_json::json;
+use sqlx::SqlitePool;
+use tera::{Tera, Context, escape_html};
+use log::{debug, error};
+
+use crate::models::{link::Link, tag::{Tag, TagWithOccurrences}, claim::authorize,
+    saved_search::{SavedSearch, SavedSearchNew, SavedSearchPage}};
+use super::paginated;
+
+fn error(e: sqlx::Error) -> HttpResponse{
+    match e{
+        sqlx::Error::RowNotFound => HttpResponse::NotFound().json(
+            json!({"code": 404, "message": "Saved search not found"})),
+        sqlx::Error::Database(_) => HttpResponse::Conflict().json(
+            json!({"code": 409, "message": "Saved search already exists"})),
+        e => HttpResponse::BadRequest().json(
+            json!({"code": 400, "message": e.to_string()})),
+    }
+}
+
+#[get("/saved")]
+pub async fn read_all(pool: web::Data<SqlitePool>) -> HttpResponse{
+    debug!("Action: Read. Path: /saved");
+    match SavedSearch::read_all(&pool).await{
+        Ok(items) => HttpResponse::Ok().json(items),
+        Err(e) => error(e),
+    }
+}
+
+#[post("/saved")]
+pub async fn create(pool: web::Data<SqlitePool>, saved: web::Json<SavedSearchNew>
+) -> HttpResponse{
+    debug!("Action: Create. Path: /saved");
+    match SavedSearch::create(&pool, &saved).await{
+        Ok(item) => HttpResponse::Created().json(item),
+        Err(e) => error(e),
+    }
+}
+
+#[get("/saved/{name}")]
+pub async fn read(pool: web::Data<SqlitePool>, name: web::Path<String>
+) -> HttpResponse{
+    debug!("Action: Read. Path: /saved/{}", name);
+    match SavedSearch::read(&pool, &name).await{
+        Ok(item) => HttpResponse::Ok().json(item),
+        Err(e) => error(e),
+    }
+}
+
+#[put("/saved/{name}")]
+pub async fn update(pool: web::Data<SqlitePool>, name: web::Path<String>,
+        saved: web::Json<SavedSearchNew>) -> HttpResponse{
+    debug!("Action: Update. Path: /saved/{}", name);
+    match SavedSearch::update(&pool, &name, &saved).await{
+        Ok(item) => HttpResponse::Ok().json(item),
+        Err(e) => error(e),
+    }
+}
+
+#[delete("/saved/{name}")]
+pub async fn delete(pool: web::Data<SqlitePool>, name: web::Path<String>
+) -> HttpResponse{
+    debug!("Action: Delete. Path: /saved/{}", name);
+    match SavedSearch::delete(&pool, &name).await{
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => error(sqlx::Error::RowNotFound),
+        Err(e) => error(e),
+    }
+}
+
+#[get("/saved/{name}/links")]
+pub async fn links(pool: web::Data<SqlitePool>, name: web::Path<String>,
+        params: web::Query<SavedSearchPage>) -> HttpResponse{
+    debug!("Action: Search. Path: /saved/{}/links", name);
+    let saved = match SavedSearch::read(&pool, &name).await{
+        Ok(saved) => saved,
+        Err(e) => return error(e),
+    };
+    match Link::search(&pool, &saved.to_search(&params)).await{
+        Ok(found) => paginated(found.page),
+        Err(e) => error(e),
+    }
+}
+
+/// HTML of a feed item, the description and its tags. Every value is escaped
+/// here and the template escapes the whole once more for the XML.
+fn summary(description: &str, tags: &[TagWithOccurrences]) -> String{
+    let mut html = format!("<p>{}</p>", escape_html(description));
+    if !tags.is_empty(){
+        html.push_str("<ul>");
+        for tag in tags{
+            html.push_str("<li");
+            if let Some(description) = &tag.description{
+                html.push_str(&format!(" title=\"{}\"", escape_html(description)));
+            }
+            if let Some(color) = &tag.color{
+                html.push_str(&format!(" style=\"color: {}\"", escape_html(color)));
+            }
+            html.push('>');
+            if let Some(icon) = &tag.icon{
+                html.push_str(&format!("{} ", escape_html(icon)));
+            }
+            html.push_str(&format!("{}</li>", escape_html(&tag.name)));
+        }
+        html.push_str("</ul>");
+    }
+    html
+}
+
+/// RSS feed of the saved search, if published. Private links are only
+/// included for authorized requests.
+#[get("/saved/{name}/feed")]
+pub async fn feed(req: HttpRequest, pool: web::Data<SqlitePool>,
+        secret: web::Data<String>, template: web::Data<Tera>,
+        name: web::Path<String>, params: web::Query<SavedSearchPage>
+) -> HttpResponse{
+    debug!("Action: Feed. Path: /saved/{}/feed", name);
+    let saved = match SavedSearch::read(&pool, &name).await{
+        Ok(saved) if saved.feed => saved,
+        Ok(_) => return error(sqlx::Error::RowNotFound),
+        Err(e) => return error(e),
+    };
+    let mut search = saved.to_search(&params);
+    if authorize(req.headers(), &secret).is_err(){
+        search.visibility = Some("public".to_string());
+    }
+    let page = match Link::search(&pool, &search).await{
+        Ok(found) => found.page,
+        Err(e) => return error(e),
+    };
+    // Tags with their description, color and icon instead of just names
+    let mut items = Vec::new();
+    for link in page.items.iter(){
+        let mut item = json!(link);
+        match Tag::read_details_for_link(&pool, link.id).await{
+            Ok(tags) => {
+                item["summary"] = json!(summary(&link.description, &tags));
+                item["tags"] = json!(tags);
+            },
+            Err(e) => {
+                error!("Can not read tags: {}", e);
+                return HttpResponse::InternalServerError().finish();
+            },
+        }
+        items.push(item);
+    }
+    let info = req.connection_info();
+    let mut context = Context::new();
+    context.insert("title", &saved.name);
+    context.insert("base", &format!("{}://{}", info.scheme(), info.host()));
+    context.insert("items", &items);
+    match template.render("feed.xml", &context){
+        Ok(content) => HttpResponse::Ok()
+            .content_type("application/rss+xml; charset=utf-8")
+            .body(content),
+        Err(e) => {
+            error!("Can not render feed: {}", e);
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
@@ -0,0 +1,65 @@
+use actix_web::{get, web, http::header, HttpRequest, HttpResponse};
+use serde_json::json;
+use sqlx::SqlitePool;
+use tera::{Tera, Context};
+use log::{debug, error};
+
+use crate::models::{claim::authorize, link::{Link, LinkWithTags}, tag::Tag};
+
+/// Private links are only resolved for authorized requests
+fn is_allowed(link: &LinkWithTags, req: &HttpRequest, secret: &str) -> bool{
+    !link.private || authorize(req.headers(), secret).is_ok()
+}
+
+fn unauthorized() -> HttpResponse{
+    HttpResponse::Unauthorized().json(
+        json!({"code": 401, "message": "Unauthorized"}))
+}
+
+#[get("/s/{shorturl}")]
+pub async fn redirect(req: HttpRequest, pool: web::Data<SqlitePool>, secret: web::Data<String>, shorturl: web::Path<String>) -> HttpResponse{
+    debug!("Action: Redirect. Path: /s/{}", shorturl);
+    match Link::read_from_shorturl(&pool, &shorturl).await{
+        Ok(link) if is_allowed(&link, &req, &secret) => HttpResponse::Found()
+            .insert_header((header::LOCATION, link.url))
+            .finish(),
+        Ok(_) => unauthorized(),
+        Err(_) => HttpResponse::NotFound().json(
+            json!({"code": 404, "message": "Not found"})),
+    }
+}
+
+#[get("/shaare/{shorturl}")]
+pub async fn note(req: HttpRequest, pool: web::Data<SqlitePool>, secret: web::Data<String>, template: web::Data<Tera>, shorturl: web::Path<String>) -> HttpResponse{
+    debug!("Action: Note. Path: /shaare/{}", shorturl);
+    let link = match Link::read_from_shorturl(&pool, &shorturl).await{
+        Ok(link) if is_allowed(&link, &req, &secret) => link,
+        Ok(_) => return unauthorized(),
+        Err(_) => return HttpResponse::NotFound().json(
+            json!({"code": 404, "message": "Not found"})),
+    };
+    let mut context = match Context::from_serialize(&link){
+        Ok(context) => context,
+        Err(e) => {
+            error!("Can not create context: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        },
+    };
+    // Tags with their description, color and icon instead of just names
+    match Tag::read_details_for_link(&pool, link.id).await{
+        Ok(tags) => context.insert("tags", &tags),
+        Err(e) => {
+            error!("Can not read tags: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        },
+    }
+    match template.render("shaare.html", &context){
+        Ok(content) => HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(content),
+        Err(e) => {
+            error!("Can not render shaare: {}", e);
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
@@ -0,0 +1,50 @@
+use actix_web::{get, post, web, HttpResponse};
+use serde_json::json;
+use sqlx::SqlitePool;
+use log::debug;
+
+use crate::models::{history::History, sync::{self, SyncParams, SyncChange,
+    SyncResult, SyncStatus}};
+
+fn error(e: sqlx::Error) -> HttpResponse{
+    HttpResponse::BadRequest().json(
+        json!({"code": 400, "message": e.to_string()}))
+}
+
+#[get("/sync")]
+pub async fn read(pool: web::Data<SqlitePool>, params: web::Query<SyncParams>
+) -> HttpResponse{
+    debug!("Action: Sync. Path: /sync");
+    match sync::changes(&pool, &params.since).await{
+        Ok(page) => HttpResponse::Ok().json(page),
+        Err(e) => error(e),
+    }
+}
+
+/// Apply the changes of the client in order. The results come in the same
+/// order, so the client can learn the ids of the links it created. A change
+/// that fails does not stop the others, its result tells why.
+#[post("/sync")]
+pub async fn apply(pool: web::Data<SqlitePool>,
+        changes: web::Json<Vec<SyncChange>>) -> HttpResponse{
+    debug!("Action: Sync. Path: /sync");
+    let mut results = Vec::new();
+    for change in changes.iter(){
+        let result = match sync::apply(&pool, change).await{
+            Ok(result) => result,
+            Err(e) => SyncResult::failed(change, &e.to_string()),
+        };
+        let payload = json!({"sync": true});
+        match (result.status, result.id){
+            (SyncStatus::Created, Some(id)) =>
+                History::created_with(&pool, id, payload).await,
+            (SyncStatus::Updated, Some(id)) =>
+                History::updated_with(&pool, id, payload).await,
+            (SyncStatus::Deleted, Some(id)) =>
+                History::deleted_with(&pool, id, payload).await,
+            _ => (),
+        }
+        results.push(result);
+    }
+    HttpResponse::Ok().json(results)
+}
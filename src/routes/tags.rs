@@ -4,28 +4,72 @@ use serde_json::json;
 use sqlx::{SqlitePool, error::Error::Database};
 use serde::Deserialize;
 use log::debug;
-use crate::models::tag::Tag;
+use crate::models::{tag::{Tag, TagSearch, TagEdit, COMPLETE_LIMIT},
+    metatag::Metatag, suggestion::Suggestion, history::History, page};
+use super::paginated;
 
 #[derive(Debug, Deserialize)]
-struct Params{
-    pub offset: Option<i32>,
+struct SuggestParams{
+    pub url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CompleteParams{
+    pub prefix: String,
     pub limit: Option<String>,
-    pub visibility: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
-struct NewName{
-    pub name: String,
+struct RelatedParams{
+    pub limit: Option<String>,
 }
 
 #[get("/tags")]
-pub async fn search(pool: web::Data<SqlitePool>, params: web::Query<Params>
+pub async fn search(pool: web::Data<SqlitePool>, params: web::Query<TagSearch>
 ) -> HttpResponse{
     debug!("Action: Search. Path: /tags");
-    let offset = &params.offset;
-    let limit = &params.limit;
-    let visibility = &params.visibility;
-    match Tag::search(&pool, offset, limit, visibility)
+    if params.tree.unwrap_or(false){
+        return match Tag::tree(&pool).await{
+            Ok(nodes) => HttpResponse::Ok().json(nodes),
+            Err(_) => HttpResponse::BadRequest().json(
+                json!({"code": 400, "message": "Invalid parameters"})),
+        };
+    }
+    match Tag::search(&pool, &params)
+        .await{
+            Ok(page) => paginated(page),
+            Err(_) => HttpResponse::BadRequest().json(
+                json!({"code": 400, "message": "Invalid parameters"})),
+        }
+}
+
+#[get("/tags/suggest")]
+pub async fn suggest(pool: web::Data<SqlitePool>, params: web::Query<SuggestParams>
+) -> HttpResponse{
+    debug!("Action: Suggest. Path: /tags/suggest");
+    let url = &params.url;
+    let metatag = match Metatag::new(url).await{
+        Some(m) => m,
+        None => Metatag::empty(url),
+    };
+    match Suggestion::suggest(&pool, url, &metatag)
+        .await{
+            Ok(items) => HttpResponse::Ok().json(items),
+            Err(_) => HttpResponse::BadRequest().json(
+                json!({"code": 400, "message": "Invalid parameters"})),
+        }
+}
+
+#[get("/tags/complete")]
+pub async fn complete(pool: web::Data<SqlitePool>, params: web::Query<CompleteParams>
+) -> HttpResponse{
+    debug!("Action: Complete. Path: /tags/complete");
+    // Always limited, even with `all`, as the levels are not indexed
+    let limit = params.limit.as_ref()
+        .and_then(|v| v.parse::<i64>().ok())
+        .filter(|limit| *limit > 0)
+        .unwrap_or(COMPLETE_LIMIT);
+    match Tag::complete(&pool, &params.prefix, limit)
         .await{
             Ok(items) => HttpResponse::Ok().json(items),
             Err(_) => HttpResponse::BadRequest().json(
@@ -44,12 +88,32 @@ pub async fn read(pool: web::Data<SqlitePool>, name: web::Path<String>) -> HttpR
         }
 }
 
+#[get("/tags/{name}/related")]
+pub async fn related(pool: web::Data<SqlitePool>, name: web::Path<String>,
+        params: web::Query<RelatedParams>) -> HttpResponse{
+    debug!("Action: Related. Path: /tags/{name}/related");
+    match Tag::related(&pool, &name, page::limit(&params.limit))
+        .await{
+            Ok(items) => HttpResponse::Ok().json(items),
+            Err(sqlx::Error::RowNotFound) => HttpResponse::NotFound().json(
+                json!({"code": 404, "message": "Tag not found"})),
+            Err(_) => HttpResponse::BadRequest().json(
+                json!({"code": 400, "message": "Invalid parameters"})),
+        }
+}
+
 #[put("/tags/{name}")]
-pub async fn update(pool: web::Data<SqlitePool>, name: web::Path<String>, body: web::Json<NewName>) -> HttpResponse{
+pub async fn update(pool: web::Data<SqlitePool>, name: web::Path<String>, body: web::Json<TagEdit>) -> HttpResponse{
     debug!("Action: Update. Path: /tags{name}");
-    match Tag::update(&pool, &name, &body.name)
+    match Tag::edit(&pool, &name, &body)
         .await{
-            Ok(items) => HttpResponse::Ok().json(items),
+            Ok(rename) => {
+                History::tag(&pool, json!({"tag": name.as_str(),
+                    "updated": &rename})).await;
+                HttpResponse::Ok().json(rename)
+            },
+            Err(sqlx::Error::RowNotFound) => HttpResponse::NotFound().json(
+                json!({"code": 404, "message": "Tag not found"})),
             Err(_) => HttpResponse::BadRequest().json(
                 json!({"code": 400, "message": "Invalid parameters"})),
         }
@@ -60,7 +124,11 @@ pub async fn delete(pool: web::Data<SqlitePool>, name: web::Path<String>) -> Htt
     debug!("Action: Delete. Path: /tags{name}");
     match Tag::delete(&pool, &name)
         .await{
-            Ok(_) => HttpResponse::NoContent().finish(),
+            Ok(_) => {
+                History::tag(&pool, json!({"tag": name.as_str(),
+                    "deleted": true})).await;
+                HttpResponse::NoContent().finish()
+            },
             Err(_) => HttpResponse::BadRequest().json(
                 json!({"code": 400, "message": "Invalid parameters"})),
         }
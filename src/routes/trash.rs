@@ -0,0 +1,52 @@
+use actix_web::{get, post, delete, web, HttpResponse};
+use serde_json::json;
+use sqlx::SqlitePool;
+use serde::Deserialize;
+use log::debug;
+
+use crate::models::{link::Link, history::History};
+
+#[derive(Debug, Deserialize)]
+struct Params{
+    pub offset: Option<i32>,
+    pub limit: Option<String>,
+}
+
+#[get("/trash")]
+pub async fn read(pool: web::Data<SqlitePool>, params: web::Query<Params>
+) -> HttpResponse{
+    debug!("Action: Read. Path: /trash");
+    match Link::read_trash(&pool, &params.offset, &params.limit).await{
+        Ok(items) => HttpResponse::Ok().json(items),
+        Err(_) => HttpResponse::BadRequest().json(
+            json!({"code": 400, "message": "Invalid parameters"})),
+    }
+}
+
+#[post("/trash/{link_id}/restore")]
+pub async fn restore(pool: web::Data<SqlitePool>, link_id: web::Path<i64>,
+) -> HttpResponse{
+    debug!("Action: Restore. Path: /trash/{}/restore", link_id);
+    match Link::restore(&pool, link_id.into_inner()).await{
+        Ok(item) => {
+            History::created_with(&pool, item.id, json!({"restored": true}))
+                .await;
+            HttpResponse::Ok().json(item)
+        },
+        Err(sqlx::Error::RowNotFound) => HttpResponse::NotFound().json(
+            json!({"code": 404, "message": "Not found"})),
+        Err(_) => HttpResponse::BadRequest().finish(),
+    }
+}
+
+#[delete("/trash/{link_id}")]
+pub async fn purge(pool: web::Data<SqlitePool>, link_id: web::Path<i64>,
+) -> HttpResponse{
+    debug!("Action: Purge. Path: /trash/{}", link_id);
+    match Link::purge_one(&pool, link_id.into_inner()).await{
+        Ok(0) => HttpResponse::NotFound().json(
+            json!({"code": 404, "message": "Not found"})),
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(_) => HttpResponse::BadRequest().finish(),
+    }
+}
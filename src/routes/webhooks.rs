@@ -0,0 +1,83 @@
+use actix_web::{get, post, put, delete, web, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::SqlitePool;
+use log::debug;
+
+use crate::models::webhook::{Webhook, WebhookNew};
+
+#[derive(Debug, Deserialize)]
+struct DeliveriesParams{
+    limit: Option<i64>,
+}
+
+fn error(e: sqlx::Error) -> HttpResponse{
+    match e{
+        sqlx::Error::RowNotFound => HttpResponse::NotFound().json(
+            json!({"code": 404, "message": "Webhook not found"})),
+        e => HttpResponse::BadRequest().json(
+            json!({"code": 400, "message": e.to_string()})),
+    }
+}
+
+#[get("/webhooks")]
+pub async fn read_all(pool: web::Data<SqlitePool>) -> HttpResponse{
+    debug!("Action: Read. Path: /webhooks");
+    match Webhook::read_all(&pool).await{
+        Ok(items) => HttpResponse::Ok().json(items),
+        Err(e) => error(e),
+    }
+}
+
+#[post("/webhooks")]
+pub async fn create(pool: web::Data<SqlitePool>, webhook: web::Json<WebhookNew>
+) -> HttpResponse{
+    debug!("Action: Create. Path: /webhooks");
+    match Webhook::create(&pool, &webhook).await{
+        Ok(item) => HttpResponse::Created().json(item),
+        Err(e) => error(e),
+    }
+}
+
+#[get("/webhooks/{id}")]
+pub async fn read(pool: web::Data<SqlitePool>, id: web::Path<i64>) -> HttpResponse{
+    debug!("Action: Read. Path: /webhooks/{}", id);
+    match Webhook::read(&pool, *id).await{
+        Ok(item) => HttpResponse::Ok().json(item),
+        Err(e) => error(e),
+    }
+}
+
+#[put("/webhooks/{id}")]
+pub async fn update(pool: web::Data<SqlitePool>, id: web::Path<i64>,
+        webhook: web::Json<WebhookNew>) -> HttpResponse{
+    debug!("Action: Update. Path: /webhooks/{}", id);
+    match Webhook::update(&pool, *id, &webhook).await{
+        Ok(item) => HttpResponse::Ok().json(item),
+        Err(e) => error(e),
+    }
+}
+
+#[delete("/webhooks/{id}")]
+pub async fn delete(pool: web::Data<SqlitePool>, id: web::Path<i64>) -> HttpResponse{
+    debug!("Action: Delete. Path: /webhooks/{}", id);
+    match Webhook::delete(&pool, *id).await{
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => error(sqlx::Error::RowNotFound),
+        Err(e) => error(e),
+    }
+}
+
+/// Log of the latest attempts to notify the webhook, the newest first
+#[get("/webhooks/{id}/deliveries")]
+pub async fn deliveries(pool: web::Data<SqlitePool>, id: web::Path<i64>,
+        params: web::Query<DeliveriesParams>) -> HttpResponse{
+    debug!("Action: Read. Path: /webhooks/{}/deliveries", id);
+    if let Err(e) = Webhook::read(&pool, *id).await{
+        return error(e);
+    }
+    match Webhook::deliveries(&pool, *id, params.limit.unwrap_or(50)).await{
+        Ok(items) => HttpResponse::Ok().json(items),
+        Err(e) => error(e),
+    }
+}